@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use ron;
+use serde_json;
+
+use export::image::write_rgba_png;
+
+/// An image to be placed on an atlas, as packed `0xAABBGGRR` pixels.
+pub struct AtlasImage {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UvFormat {
+    Json,
+    Ron
+}
+
+pub struct AtlasOptions {
+    /// Width of every atlas, and the height it may grow to before another is started.
+    pub max_size: u32,
+    /// Empty pixels left between neighbouring images.
+    pub padding: u32,
+    /// Pixels of repeated edge added around each image to stop filtering bleeding in.
+    pub extrude: u32,
+    pub format: UvFormat
+}
+
+/// Where an image ended up: its pixel rectangle on atlas `atlas`, and the same rectangle
+/// as normalised texture coordinates.
+#[derive(Serialize)]
+pub struct UvRect {
+    pub atlas: usize,
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32
+}
+
+pub struct Atlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>
+}
+
+/// Packs images onto as many atlases as needed, tallest first in rows, returning the
+/// atlases and a UV table keyed by image name. Images with no pixels are left out, and a
+/// name used more than once gets the image's index appended, or a higher number if that
+/// is taken too.
+pub fn build(images: &[AtlasImage], opts: &AtlasOptions) -> Result<(Vec<Atlas>, BTreeMap<String, UvRect>), io::Error> {
+    let border = opts.extrude * 2 + opts.padding;
+
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(|&a, &b| images[b].height.cmp(&images[a].height).then(images[b].width.cmp(&images[a].width)));
+
+    // First pass: assign every image a spot on some atlas
+    let mut spots = vec![None; images.len()];
+    let mut heights = vec![0];
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+
+    for &i in order.iter() {
+        if images[i].width == 0 || images[i].height == 0 {
+            continue;
+        }
+
+        let (w, h) = (images[i].width + border, images[i].height + border);
+        if w > opts.max_size || h > opts.max_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("{} is {}x{}, too big for a {} atlas", images[i].name,
+                                              images[i].width, images[i].height, opts.max_size)));
+        }
+
+        if x + w > opts.max_size {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        if y + h > opts.max_size {
+            heights.push(0);
+            x = 0;
+            y = 0;
+            row_height = 0;
+        }
+
+        let atlas = heights.len() - 1;
+        spots[i] = Some((atlas, x + opts.extrude, y + opts.extrude));
+        x += w;
+        row_height = row_height.max(h);
+        heights[atlas] = heights[atlas].max(y + row_height);
+    }
+
+    let mut atlases: Vec<Atlas> = heights.iter().map(|&h| {
+        let height = h.max(1).next_power_of_two().min(opts.max_size);
+        Atlas { width: opts.max_size, height, pixels: vec![0; (opts.max_size * height) as usize] }
+    }).collect();
+
+    // Second pass: copy pixels across and work out UVs now the atlas sizes are known
+    let mut uvs = BTreeMap::new();
+    for (i, img) in images.iter().enumerate() {
+        let (a, ox, oy) = match spots[i] {
+            Some(spot) => spot,
+            None => continue
+        };
+        let atlas = &mut atlases[a];
+        let e = opts.extrude as i64;
+
+        for y in -e..(img.height as i64 + e) {
+            for x in -e..(img.width as i64 + e) {
+                let sx = x.max(0).min(img.width as i64 - 1) as u32;
+                let sy = y.max(0).min(img.height as i64 - 1) as u32;
+                let dx = (ox as i64 + x) as u32;
+                let dy = (oy as i64 + y) as u32;
+                atlas.pixels[(dy * atlas.width + dx) as usize] = img.pixels[(sy * img.width + sx) as usize];
+            }
+        }
+
+        let (aw, ah) = (atlas.width as f32, atlas.height as f32);
+        let rect = UvRect {
+            atlas: a, x: ox, y: oy, w: img.width, h: img.height,
+            u0: ox as f32 / aw, v0: oy as f32 / ah,
+            u1: (ox + img.width) as f32 / aw, v1: (oy + img.height) as f32 / ah
+        };
+
+        let (mut key, mut n) = (img.name.clone(), i);
+        while uvs.contains_key(&key) {
+            key = format!("{}_{}", img.name, n);
+            n += 1;
+        }
+        uvs.insert(key, rect);
+    }
+
+    Ok((atlases, uvs))
+}
+
+/// Writes `atlas_<n>.png` for each atlas and the UV table as `atlas.json` or `atlas.ron`.
+pub fn write(atlases: &[Atlas], uvs: &BTreeMap<String, UvRect>, opts: &AtlasOptions, dir: &Path) -> Result<(), io::Error> {
+    for (i, atlas) in atlases.iter().enumerate() {
+        let file = File::create(dir.join(format!("atlas_{}.png", i)))?;
+        write_rgba_png(BufWriter::new(file), atlas.width, atlas.height, &atlas.pixels)?;
+    }
+
+    match opts.format {
+        UvFormat::Json => {
+            let file = File::create(dir.join("atlas.json"))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), uvs)?;
+        },
+        UvFormat::Ron => {
+            let text = ron::ser::to_string_pretty(uvs, ron::ser::PrettyConfig::default())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            File::create(dir.join("atlas.ron"))?.write_all(text.as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, AtlasImage, AtlasOptions, UvFormat};
+
+    fn image(name: &str, width: u32, height: u32) -> AtlasImage {
+        AtlasImage { name: String::from(name), width, height, pixels: vec![0xff00ff00; (width * height) as usize] }
+    }
+
+    fn options(max_size: u32) -> AtlasOptions {
+        AtlasOptions { max_size, padding: 0, extrude: 1, format: UvFormat::Json }
+    }
+
+    #[test]
+    fn height_stays_within_max_size() {
+        let (atlases, _) = build(&[image("a", 10, 90)], &options(100)).unwrap();
+        assert_eq!((atlases[0].width, atlases[0].height), (100, 100));
+    }
+
+    #[test]
+    fn empty_images_are_left_out() {
+        let (_, uvs) = build(&[image("empty", 0, 16), image("a", 4, 4)], &options(64)).unwrap();
+        assert_eq!(uvs.keys().collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn duplicate_names_get_unique_keys() {
+        let images = [image("a", 4, 4), image("a_1", 4, 4), image("a", 4, 4)];
+        let (_, uvs) = build(&images, &options(64)).unwrap();
+        assert_eq!(uvs.keys().collect::<Vec<_>>(), ["a", "a_1", "a_2"]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use png;
+
+use gob::{Bitmap, Palette};
+use postprocess::convert;
+
+/// Writes 8 bit palette indices as an indexed PNG. With `transparent` set, that index is
+/// marked fully transparent.
+pub fn write_indexed_png<W: Write>(w: W, width: u32, height: u32, palette: &Palette, data: &[u8],
+                                   transparent: Option<u8>) -> Result<(), io::Error> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.to_rgb());
+
+    if let Some(t) = transparent {
+        let mut trns = vec![255u8; t as usize + 1];
+        trns[t as usize] = 0;
+        encoder.set_trns(trns);
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+
+    Ok(())
+}
+
+/// Reads an 8 bit indexed PNG as its width, height and palette indices.
+pub fn read_indexed_png<R: Read>(r: R) -> Result<(u32, u32, Vec<u8>), io::Error> {
+    let mut decoder = png::Decoder::new(r);
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+
+    let mut data = vec![0u8; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut data)?;
+    if frame.color_type != png::ColorType::Indexed || frame.bit_depth != png::BitDepth::Eight {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("expected an 8 bit indexed PNG, not {:?} at {:?}", frame.color_type, frame.bit_depth)));
+    }
+    data.truncate(frame.buffer_size());
+
+    Ok((frame.width, frame.height, data))
+}
+
+/// Writes packed `0xAABBGGRR` pixels as an RGBA PNG.
+pub fn write_rgba_png<W: Write>(w: W, width: u32, height: u32, pixels: &[u32]) -> Result<(), io::Error> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut data = Vec::with_capacity(pixels.len() * 4);
+    for &p in pixels {
+        let (r, g, b, a) = convert::unpack(p);
+        data.extend_from_slice(&[r, g, b, a]);
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
+
+/// Writes the old bitmap, the new one and a map of where they differ side by side. Changed
+/// pixels are red on a dimmed copy of the new bitmap.
+pub fn write_pixel_diff(a: &Bitmap, b: &Bitmap, old_pal: &Palette, new_pal: &Palette, path: &Path) -> Result<(), io::Error> {
+    let (w, h) = (a.width, a.height);
+    let (pa, pb) = (convert::to_rgba(a, old_pal), convert::to_rgba(b, new_pal));
+    let mut out = vec![0u32; (w * 3 * h) as usize];
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = (y * w + x) as usize;
+            let row = (y * w * 3) as usize;
+            out[row + x as usize] = pa[i];
+            out[row + (w + x) as usize] = pb[i];
+            out[row + (w * 2 + x) as usize] = if a.pixel(x, y) != b.pixel(x, y) {
+                0xff0000ff
+            } else {
+                let (r, g, b, _) = convert::unpack(pb[i]);
+                let grey = (r as u32 + g as u32 + b as u32) / 9;
+                0xff000000 | grey << 16 | grey << 8 | grey
+            };
+        }
+    }
+
+    write_rgba_png(BufWriter::new(File::create(path)?), w * 3, h, &out)
+}
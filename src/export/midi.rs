@@ -0,0 +1,183 @@
+use std::io;
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, BigEndian, ReadBytesExt, WriteBytesExt};
+
+use gob::music::{MusicFormat, MusicTrack};
+
+/// MUS plays at 140 ticks a second; 70 ticks per quarter note at 120 bpm matches that.
+const MIDI_DIVISION: u16 = 70;
+const MIDI_TEMPO: u32 = 500000;
+
+/// MUS controller numbers 1-9 mapped to MIDI controllers. 0 is a program change.
+const MUS_CONTROLLERS: [u8; 10] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67];
+
+/// MUS system events 10-14 mapped to MIDI channel mode controllers.
+const MUS_SYSTEM_EVENTS: [u8; 5] = [120, 123, 126, 127, 121];
+
+/// Gives the track as a Standard MIDI File, converting it if need be. Returns `None` for
+/// formats that can't be converted, which are best written out as they are.
+pub fn to_midi(track: &MusicTrack) -> Result<Option<Vec<u8>>, io::Error> {
+    match track.format {
+        MusicFormat::Midi => Ok(Some(track.data.clone())),
+        MusicFormat::Mus => mus_to_midi(&track.data).map(Some),
+        _ => Ok(None)
+    }
+}
+
+/// Converts a DMX MUS file to a type 0 Standard MIDI File.
+pub fn mus_to_midi(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut buf = Cursor::new(data);
+    let mut magic = [0u8; 4];
+    buf.read_exact(&mut magic)?;
+    if &magic != b"MUS\x1a" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a MUS file"));
+    }
+    let _score_len = buf.read_u16::<LittleEndian>()?;
+    let score_start = buf.read_u16::<LittleEndian>()?;
+    buf.set_position(score_start as u64);
+
+    let mut track = Vec::new();
+    write_varlen(&mut track, 0);
+    track.extend_from_slice(&[0xff, 0x51, 0x03]);
+    track.extend_from_slice(&[(MIDI_TEMPO >> 16) as u8, (MIDI_TEMPO >> 8) as u8, MIDI_TEMPO as u8]);
+
+    let mut volumes = [127u8; 16];
+    let mut delay = 0;
+
+    loop {
+        let desc = buf.read_u8()?;
+        let last = desc & 0x80 != 0;
+        let channel = midi_channel(desc & 0x0f);
+        let mut event: Vec<u8> = Vec::new();
+
+        match (desc >> 4) & 0x07 {
+            0 => event.extend_from_slice(&[0x90 | channel, buf.read_u8()? & 0x7f, 0]),
+            1 => {
+                let note = buf.read_u8()?;
+                if note & 0x80 != 0 {
+                    volumes[channel as usize] = buf.read_u8()? & 0x7f;
+                }
+                event.extend_from_slice(&[0x90 | channel, note & 0x7f, volumes[channel as usize]]);
+            },
+            2 => {
+                let bend = buf.read_u8()?;
+                event.extend_from_slice(&[0xe0 | channel, (bend & 1) << 6, bend >> 1]);
+            },
+            3 => {
+                let sys = buf.read_u8()? as usize;
+                if (10..=14).contains(&sys) {
+                    event.extend_from_slice(&[0xb0 | channel, MUS_SYSTEM_EVENTS[sys - 10], 0]);
+                }
+            },
+            4 => {
+                let ctrl = buf.read_u8()? as usize;
+                let value = buf.read_u8()? & 0x7f;
+                if ctrl == 0 {
+                    event.extend_from_slice(&[0xc0 | channel, value]);
+                } else if ctrl < MUS_CONTROLLERS.len() {
+                    event.extend_from_slice(&[0xb0 | channel, MUS_CONTROLLERS[ctrl], value]);
+                }
+            },
+            5 => {},
+            6 => break,
+            _ => {
+                buf.read_u8()?;
+            }
+        }
+
+        if !event.is_empty() {
+            write_varlen(&mut track, delay);
+            track.extend_from_slice(&event);
+            delay = 0;
+        }
+
+        if last {
+            delay += read_varlen(&mut buf)?;
+        }
+    }
+
+    write_varlen(&mut track, delay);
+    track.extend_from_slice(&[0xff, 0x2f, 0x00]);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"MThd");
+    out.write_u32::<BigEndian>(6)?;
+    out.write_u16::<BigEndian>(0)?;
+    out.write_u16::<BigEndian>(1)?;
+    out.write_u16::<BigEndian>(MIDI_DIVISION)?;
+    out.extend_from_slice(b"MTrk");
+    out.write_u32::<BigEndian>(track.len() as u32)?;
+    out.extend_from_slice(&track);
+
+    Ok(out)
+}
+
+/// MUS puts percussion on channel 15, MIDI on channel 9, so the two swap.
+fn midi_channel(mus: u8) -> u8 {
+    match mus {
+        15 => 9,
+        9 => 15,
+        c => c
+    }
+}
+
+fn read_varlen<R: Read>(buf: &mut R) -> Result<u32, io::Error> {
+    let mut value = 0u32;
+    loop {
+        let b = buf.read_u8()?;
+        value = (value << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn write_varlen(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut v = value >> 7;
+    while v > 0 {
+        bytes.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    bytes.reverse();
+    out.extend_from_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mus_to_midi;
+
+    #[test]
+    fn converts_mus_events() {
+        let mut mus = b"MUS\x1a\0\0\x10\0".to_vec();
+        mus.resize(16, 0);
+        mus.extend_from_slice(&[
+            0x90, 0x80 | 60, 100, 10,   // play note 60 at volume 100 on channel 0, then wait 10
+            0x00, 60,                   // release it
+            0x1f, 35,                   // play note 35 on percussion channel 15
+            0x19, 40,                   // and note 40 on channel 9
+            0x40, 3, 90,                // channel 0 volume
+            0x40, 0, 5,                 // channel 0 instrument
+            0x60                        // end
+        ]);
+
+        let midi = mus_to_midi(&mus).unwrap();
+        assert_eq!(&midi[..22], b"MThd\0\0\0\x06\0\0\0\x01\0\x46MTrk\0\0\0\x22");
+        assert_eq!(&midi[22..], &[
+            0, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0, 0x90, 60, 100,
+            10, 0x90, 60, 0,
+            0, 0x99, 35, 127,
+            0, 0x9f, 40, 127,
+            0, 0xb0, 7, 90,
+            0, 0xc0, 5,
+            0, 0xff, 0x2f, 0x00
+        ][..]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(mus_to_midi(b"MThd\0\0\0\x06").is_err());
+    }
+}
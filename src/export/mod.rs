@@ -0,0 +1,10 @@
+pub mod atlas;
+pub mod image;
+pub mod midi;
+pub mod objects;
+pub mod palette;
+pub mod quake;
+pub mod render;
+pub mod sprites;
+pub mod svg;
+pub mod udmf;
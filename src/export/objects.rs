@@ -0,0 +1,42 @@
+use std::io;
+use std::io::Write;
+
+use serde_json;
+
+use gob::catalogue::{Counts, Placement};
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+/// One row per object, with its difficulties separated by `;`.
+pub fn write_csv<W: Write>(placements: &[Placement], w: &mut W) -> Result<(), io::Error> {
+    writeln!(w, "index,kind,name,category,x,y,z,angle,flags,difficulties")?;
+    for p in placements.iter() {
+        writeln!(w, "{},{},{},{},{},{},{},{},0x{:04x},{}", p.index, p.kind, csv_field(&p.name), p.category.name(),
+                 p.x, p.y, p.z, p.angle, p.flags, csv_field(&p.difficulties.join(";")))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct LevelObjects<'a> {
+    level: usize,
+    name: &'a str,
+    counts: &'a Counts,
+    objects: &'a [Placement]
+}
+
+/// The level's objects and their counts per difficulty.
+pub fn write_json<W: Write>(level: usize, name: &str, placements: &[Placement], counts: &Counts, w: W) -> Result<(), io::Error> {
+    let out = LevelObjects { level, name, counts, objects: placements };
+    serde_json::to_writer_pretty(w, &out)?;
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use gob::{Palette, PaletteDepth};
+use export::image::write_indexed_png;
+
+/// Swatch images are 16x16 colours, each drawn as a `SWATCH_CELL` pixel square.
+const SWATCH_CELL: u32 = 16;
+
+/// Paint Shop Pro palette.
+pub fn write_jasc<W: Write>(p: &Palette, w: &mut W) -> Result<(), io::Error> {
+    write!(w, "JASC-PAL\r\n0100\r\n256\r\n")?;
+    for i in 0..256 {
+        write!(w, "{} {} {}\r\n", p.r[i], p.g[i], p.b[i])?;
+    }
+
+    Ok(())
+}
+
+/// Reads a Paint Shop Pro palette of up to 256 colours. `depth` is how the palette should
+/// be stored when written back to a GOB.
+pub fn read_jasc<R: BufRead>(r: R, depth: PaletteDepth) -> Result<Palette, io::Error> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("JASC palette: {}", msg));
+    let mut lines = r.lines();
+    let mut next = || lines.next().unwrap_or_else(|| Err(invalid("unexpected end")));
+
+    if next()?.trim() != "JASC-PAL" {
+        return Err(invalid("missing header"));
+    }
+    next()?;
+    let count: usize = next()?.trim().parse().map_err(|_| invalid("bad colour count"))?;
+    if count > 256 {
+        return Err(invalid("more than 256 colours"));
+    }
+
+    let mut p = Palette { r: [0; 256], g: [0; 256], b: [0; 256], depth };
+    for i in 0..count {
+        let line = next()?;
+        let rgb: Vec<u8> = line.split_whitespace().map(|c| c.parse()).collect::<Result<_, _>>()
+            .map_err(|_| invalid(&format!("bad colour {}", i)))?;
+        if rgb.len() != 3 {
+            return Err(invalid(&format!("bad colour {}", i)));
+        }
+        p.r[i] = rgb[0];
+        p.g[i] = rgb[1];
+        p.b[i] = rgb[2];
+    }
+
+    Ok(p)
+}
+
+/// GIMP palette.
+pub fn write_gpl<W: Write>(p: &Palette, name: &str, w: &mut W) -> Result<(), io::Error> {
+    write!(w, "GIMP Palette\nName: {}\nColumns: 16\n#\n", name)?;
+    for i in 0..256 {
+        writeln!(w, "{:3} {:3} {:3}\tIndex {}", p.r[i], p.g[i], p.b[i], i)?;
+    }
+
+    Ok(())
+}
+
+/// Adobe colour table: 256 packed RGB triples.
+pub fn write_act<W: Write>(p: &Palette, w: &mut W) -> Result<(), io::Error> {
+    w.write_all(&p.to_rgb())
+}
+
+/// Indexed PNG showing the palette as a 16x16 grid of swatches, colour 0 at the top left.
+pub fn write_swatch<W: Write>(p: &Palette, w: W) -> Result<(), io::Error> {
+    let size = 16 * SWATCH_CELL;
+    let mut data = vec![0u8; (size * size) as usize];
+    for y in 0..size {
+        for x in 0..size {
+            data[(y * size + x) as usize] = ((y / SWATCH_CELL) * 16 + x / SWATCH_CELL) as u8;
+        }
+    }
+
+    write_indexed_png(w, size, size, p, &data, None)
+}
+
+/// Writes `<name>.pal`, `.gpl`, `.act` and `.png` for a palette into `dir`.
+pub fn export(p: &Palette, dir: &Path, name: &str) -> Result<(), io::Error> {
+    write_jasc(p, &mut BufWriter::new(File::create(dir.join(format!("{}.pal", name)))?))?;
+    write_gpl(p, name, &mut BufWriter::new(File::create(dir.join(format!("{}.gpl", name)))?))?;
+    write_act(p, &mut BufWriter::new(File::create(dir.join(format!("{}.act", name)))?))?;
+    write_swatch(p, BufWriter::new(File::create(dir.join(format!("{}.png", name)))?))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use gob::{Palette, PaletteDepth};
+    use export::image::read_indexed_png;
+    use super::{read_jasc, write_act, write_gpl, write_jasc, write_swatch};
+
+    fn palette() -> Palette {
+        let mut p = Palette { r: [0; 256], g: [0; 256], b: [0; 256], depth: PaletteDepth::Rgb8 };
+        for i in 0..256 {
+            p.r[i] = i as u8;
+            p.g[i] = 255 - i as u8;
+            p.b[i] = (i * 3) as u8;
+        }
+        p
+    }
+
+    #[test]
+    fn jasc_round_trip() {
+        let mut text = Vec::new();
+        write_jasc(&palette(), &mut text).unwrap();
+        assert!(text.starts_with(b"JASC-PAL\r\n0100\r\n256\r\n0 255 0\r\n1 254 3\r\n"));
+
+        let back = read_jasc(Cursor::new(text), PaletteDepth::Vga6).unwrap();
+        assert_eq!(back.to_rgb(), palette().to_rgb());
+        assert_eq!(back.depth, PaletteDepth::Vga6);
+    }
+
+    #[test]
+    fn rejects_broken_jasc() {
+        for text in ["RIFF\r\n", "JASC-PAL\r\n0100\r\n257\r\n", "JASC-PAL\r\n0100\r\n2\r\n1 2 3\r\n", "JASC-PAL\r\n0100\r\n1\r\n1 2\r\n",
+                     "JASC-PAL\r\n0100\r\n1\r\n1 2 256\r\n"].iter() {
+            assert!(read_jasc(Cursor::new(text), PaletteDepth::Rgb8).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn gimp_and_adobe_palettes() {
+        let mut gpl = Vec::new();
+        write_gpl(&palette(), "test", &mut gpl).unwrap();
+        let gpl = String::from_utf8(gpl).unwrap();
+        assert!(gpl.starts_with("GIMP Palette\nName: test\nColumns: 16\n#\n  0 255   0\tIndex 0\n"));
+        assert_eq!(gpl.lines().count(), 4 + 256);
+
+        let mut act = Vec::new();
+        write_act(&palette(), &mut act).unwrap();
+        assert_eq!(act.len(), 768);
+        assert_eq!(&act[3..6], &[1, 254, 3]);
+    }
+
+    #[test]
+    fn swatch_has_a_cell_per_colour() {
+        let mut png = Vec::new();
+        write_swatch(&palette(), &mut png).unwrap();
+
+        let (width, height, data) = read_indexed_png(Cursor::new(png)).unwrap();
+        assert_eq!((width, height), (256, 256));
+        let at = |x: usize, y: usize| data[y * 256 + x];
+        assert_eq!((at(0, 0), at(15, 15), at(16, 0), at(0, 16), at(255, 255)), (0, 0, 1, 16, 255));
+    }
+}
@@ -0,0 +1,391 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use gob::{clean_name, Bitmap, Level, Palette, RowOrder};
+use gob::level::NO_ZONE;
+use gob::link::{self, LinkedTexture};
+use gob::map::Gob;
+use export::image;
+use export::render::Camera;
+use export::udmf::lump_names;
+
+/// Thickness of the solid brushes behind walls, under the lowest floor and over the
+/// highest ceiling.
+const SHELL_THICKNESS: i32 = 16;
+
+/// How far above the floor a Quake player's origin sits.
+const PLAYER_ORIGIN_HEIGHT: i32 = 24;
+
+/// WAD3 textures must be a multiple of this in both directions.
+const MIP_ALIGN: u32 = 16;
+
+const MIP_LEVELS: usize = 4;
+
+/// WAD3 lump type of a mip texture.
+const TYPE_MIPTEX: u8 = 0x43;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureOutput {
+    /// A single `textures.wad` in WAD3 format, as used by Half-Life and TrenchBroom.
+    Wad3,
+    /// One indexed PNG per texture, for engines and editors that load loose images.
+    Png
+}
+
+/// A texture as the map refers to it: its name and how many map units each texel covers.
+pub struct MapTexture {
+    pub name: String,
+    pub bitmap: Bitmap<'static>,
+    pub scale: (f32, f32)
+}
+
+/// Loads every texture under its lump name. WAD3 needs sizes in multiples of 16, so for
+/// it textures are resampled up and their scale adjusted to cover the same area.
+pub fn map_textures(gob: &Gob, textures: &[LinkedTexture], output: TextureOutput) -> Result<Vec<MapTexture>, io::Error> {
+    let names = lump_names(textures);
+    let mut out = Vec::with_capacity(textures.len());
+
+    for (t, name) in textures.iter().zip(names) {
+        let bitmap = match t.bitmap {
+            Some(h) => gob.bitmap(h.index())?,
+            None => link::placeholder_bitmap(&t.texture.name)
+        };
+        let size = if t.texture.pixel_size > 0.0 && t.texture.pixel_size.is_finite() { t.texture.pixel_size } else { 1.0 };
+
+        let (width, height) = match output {
+            TextureOutput::Wad3 => (mip_align(bitmap.width), mip_align(bitmap.height)),
+            TextureOutput::Png => (bitmap.width, bitmap.height)
+        };
+        let scale = (size * bitmap.width.max(1) as f32 / width as f32, size * bitmap.height.max(1) as f32 / height as f32);
+
+        out.push(MapTexture { name, bitmap: resample(&bitmap, width, height), scale });
+    }
+
+    Ok(out)
+}
+
+fn mip_align(n: u32) -> u32 {
+    (n.div_ceil(MIP_ALIGN) * MIP_ALIGN).max(MIP_ALIGN)
+}
+
+/// Nearest neighbour resize.
+fn resample(bmp: &Bitmap, width: u32, height: u32) -> Bitmap<'static> {
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            if bmp.width == 0 || bmp.height == 0 {
+                data.push(0);
+            } else {
+                data.push(bmp.pixel(x * bmp.width / width, y * bmp.height / height));
+            }
+        }
+    }
+
+    Bitmap::new(bmp.name.clone(), width, height, bmp.flags, RowOrder::TopDown, data)
+}
+
+/// Writes textures as a WAD3 of mip textures, each carrying its own copy of the palette.
+pub fn write_wad3<W: Write>(w: &mut W, textures: &[MapTexture], palette: &Palette) -> Result<(), io::Error> {
+    let mut lumps = Vec::with_capacity(textures.len());
+    for t in textures.iter() {
+        lumps.push(miptex(t, palette)?);
+    }
+
+    let data_size: usize = lumps.iter().map(|l| l.len()).sum();
+    w.write_all(b"WAD3")?;
+    w.write_i32::<LittleEndian>(lumps.len() as i32)?;
+    w.write_i32::<LittleEndian>(12 + data_size as i32)?;
+    for l in lumps.iter() {
+        w.write_all(l)?;
+    }
+
+    let mut pos = 12;
+    for (t, l) in textures.iter().zip(lumps.iter()) {
+        w.write_i32::<LittleEndian>(pos)?;
+        w.write_i32::<LittleEndian>(l.len() as i32)?;
+        w.write_i32::<LittleEndian>(l.len() as i32)?;
+        w.write_u8(TYPE_MIPTEX)?;
+        w.write_u8(0)?;
+        w.write_u16::<LittleEndian>(0)?;
+        w.write_all(&texture_name(&t.name))?;
+        pos += l.len() as i32;
+    }
+
+    Ok(())
+}
+
+fn texture_name(name: &str) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for (i, b) in name.bytes().take(15).enumerate() {
+        out[i] = b;
+    }
+
+    out
+}
+
+/// A mip texture lump: the header, four mip levels, then the palette.
+fn miptex(t: &MapTexture, palette: &Palette) -> Result<Vec<u8>, io::Error> {
+    let (width, height) = (t.bitmap.width, t.bitmap.height);
+    let header_size = 16 + 4 + 4 + 4 * MIP_LEVELS as u32;
+
+    let mut out = Vec::new();
+    out.write_all(&texture_name(&t.name))?;
+    out.write_u32::<LittleEndian>(width)?;
+    out.write_u32::<LittleEndian>(height)?;
+
+    let mut offset = header_size;
+    for level in 0..MIP_LEVELS {
+        out.write_u32::<LittleEndian>(offset)?;
+        offset += (width >> level) * (height >> level);
+    }
+
+    for level in 0..MIP_LEVELS {
+        let mip = resample(&t.bitmap, width >> level, height >> level);
+        out.extend_from_slice(&mip.top_down());
+    }
+
+    out.write_u16::<LittleEndian>(256)?;
+    out.extend_from_slice(&palette.to_rgb());
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+
+    Ok(out)
+}
+
+/// Writes each texture to `dir` as `<name>.png`.
+pub fn write_pngs(dir: &Path, textures: &[MapTexture], palette: &Palette) -> Result<(), io::Error> {
+    for t in textures.iter() {
+        let file = BufWriter::new(File::create(dir.join(format!("{}.png", t.name)))?);
+        image::write_indexed_png(file, t.bitmap.width, t.bitmap.height, palette, &t.bitmap.top_down(), None)?;
+    }
+
+    Ok(())
+}
+
+/// Texture projection of a brush face: the texture name, then the Valve 220 u and v axes
+/// with their offsets, and the scale.
+struct Projection<'a> {
+    name: &'a str,
+    u: (f32, f32, f32, f32),
+    v: (f32, f32, f32, f32),
+    scale: (f32, f32)
+}
+
+impl<'a> Projection<'a> {
+    fn missing() -> Projection<'static> {
+        Projection { name: "__TB_empty", u: (1.0, 0.0, 0.0, 0.0), v: (0.0, -1.0, 0.0, 0.0), scale: (1.0, 1.0) }
+    }
+
+    /// Top-down projection for floors and ceilings, aligned to the map grid.
+    fn flat(t: &'a MapTexture) -> Projection<'a> {
+        Projection { name: &t.name, u: (1.0, 0.0, 0.0, 0.0), v: (0.0, -1.0, 0.0, 0.0), scale: t.scale }
+    }
+
+    /// Projection along a wall from `a` to `b`, starting at `a` and hanging from `top`.
+    fn wall(t: &'a MapTexture, a: (i32, i32), b: (i32, i32), top: i32) -> Projection<'a> {
+        let (dx, dy) = ((b.0 - a.0) as f32, (b.1 - a.1) as f32);
+        let len = (dx * dx + dy * dy).sqrt().max(1.0);
+        let (ux, uy) = (dx / len, dy / len);
+        let u_offset = -(a.0 as f32 * ux + a.1 as f32 * uy) / t.scale.0;
+
+        Projection { name: &t.name, u: (ux, uy, 0.0, u_offset), v: (0.0, 0.0, -1.0, top as f32 / t.scale.1), scale: t.scale }
+    }
+}
+
+/// Writes one face of a brush, given three points on its plane ordered clockwise as seen
+/// from outside.
+fn write_face<W: Write>(w: &mut W, p: [(f32, f32, f32); 3], proj: &Projection) -> Result<(), io::Error> {
+    for &(x, y, z) in p.iter() {
+        write!(w, "( {} {} {} ) ", num(x), num(y), num(z))?;
+    }
+    writeln!(w, "{} [ {} {} {} {} ] [ {} {} {} {} ] 0 {} {}", proj.name,
+             num(proj.u.0), num(proj.u.1), num(proj.u.2), num(proj.u.3),
+             num(proj.v.0), num(proj.v.1), num(proj.v.2), num(proj.v.3),
+             num(proj.scale.0), num(proj.scale.1))
+}
+
+fn num(f: f32) -> String {
+    let s = format!("{:.4}", f);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { String::from("0") } else { String::from(s) }
+}
+
+/// Writes a vertical prism over a counter-clockwise polygon from `bottom` to `top`. `side`
+/// gives the projection of the face along each edge, starting from `poly[i]`.
+fn write_prism<'a, W, F>(w: &mut W, poly: &[(f32, f32)], bottom: f32, top: f32, top_proj: &Projection,
+                         bottom_proj: &Projection, side: F) -> Result<(), io::Error>
+    where W: Write, F: Fn(usize) -> Projection<'a>
+{
+    writeln!(w, "{{")?;
+    write_face(w, [(0.0, 0.0, top), (0.0, 1.0, top), (1.0, 0.0, top)], top_proj)?;
+    write_face(w, [(0.0, 0.0, bottom), (1.0, 0.0, bottom), (0.0, 1.0, bottom)], bottom_proj)?;
+    for i in 0..poly.len() {
+        let (a, b) = (poly[i], poly[(i + 1) % poly.len()]);
+        write_face(w, [(b.0, b.1, bottom), (a.0, a.1, bottom), (a.0, a.1, top)], &side(i))?;
+    }
+    writeln!(w, "}}")
+}
+
+/// Writes a level as a Valve 220 `.map`. The world is built from solid brushes around the
+/// level's open space: a floor slab under each zone and a ceiling slab over it, both split
+/// into convex pieces, and a brush behind every one-sided wall. Steps between zones take the
+/// texture of the wall they sit on. Objects become `info_notnull` entities carrying their
+/// kind and flags.
+pub fn write_map<W: Write>(level: &Level, textures: &[MapTexture], wad: Option<&str>, w: &mut W) -> Result<(), io::Error> {
+    let texture = |idx: u16| textures.get(idx as usize);
+    let flat = |idx: u16| texture(idx).map_or(Projection::missing(), Projection::flat);
+
+    let base = level.zones.iter().map(|z| z.floor as i32).min().unwrap_or(0) - SHELL_THICKNESS;
+    let roof = level.zones.iter().map(|z| z.ceiling as i32).max().unwrap_or(0) + SHELL_THICKNESS;
+    let point = |v: u16| level.vertex(v).map_or((0, 0), |v| (v.x as i32, v.y as i32));
+
+    writeln!(w, "// Game: Quake")?;
+    writeln!(w, "// Format: Valve")?;
+    writeln!(w, "// {}", clean_name(&level.name))?;
+    writeln!(w, "{{")?;
+    writeln!(w, "\"classname\" \"worldspawn\"")?;
+    writeln!(w, "\"mapversion\" \"220\"")?;
+    if let Some(wad) = wad {
+        writeln!(w, "\"wad\" \"{}\"", wad)?;
+    }
+
+    for (z, zone) in level.zones.iter().enumerate() {
+        // The wall along each edge, if there is one, gives the texture of the step up to
+        // the next zone's floor or down to its ceiling.
+        let edge_wall = |a: u16, b: u16| level.walls.iter().find(|w| (w.v1 == a && w.v2 == b) || (w.v1 == b && w.v2 == a));
+
+        for piece in level.convex_pieces(z as u16) {
+            let poly: Vec<(f32, f32)> = piece.iter().map(|&v| { let p = point(v); (p.0 as f32, p.1 as f32) }).collect();
+            let side = |i: usize| {
+                let (a, b) = (piece[i], piece[(i + 1) % piece.len()]);
+                match edge_wall(a, b).and_then(|w| texture(w.texture)) {
+                    Some(t) => Projection::wall(t, point(a), point(b), zone.ceiling as i32),
+                    None => flat(zone.floor_texture)
+                }
+            };
+
+            write_prism(w, &poly, base as f32, zone.floor as f32, &flat(zone.floor_texture), &flat(zone.floor_texture), side)?;
+            write_prism(w, &poly, zone.ceiling as f32, roof as f32, &flat(zone.ceiling_texture), &flat(zone.ceiling_texture), side)?;
+        }
+    }
+
+    for wall in level.walls.iter().filter(|w| w.back == NO_ZONE || w.back as usize >= level.zones.len()) {
+        let (a, b) = (point(wall.v1), point(wall.v2));
+        let (dx, dy) = ((b.0 - a.0) as f32, (b.1 - a.1) as f32);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            continue;
+        }
+
+        // The front is on the wall's right, so the solid side is on its left
+        let (nx, ny) = (-dy / len * SHELL_THICKNESS as f32, dx / len * SHELL_THICKNESS as f32);
+        let (af, bf) = ((a.0 as f32, a.1 as f32), (b.0 as f32, b.1 as f32));
+        let poly = [af, bf, (bf.0 + nx, bf.1 + ny), (af.0 + nx, af.1 + ny)];
+        let top = level.zones.get(wall.front as usize).map_or(roof, |z| z.ceiling as i32);
+
+        let proj = match texture(wall.texture) {
+            Some(t) => Projection::wall(t, a, b, top),
+            None => Projection::missing()
+        };
+        let side = |_| match texture(wall.texture) {
+            Some(t) => Projection::wall(t, a, b, top),
+            None => Projection::missing()
+        };
+        write_prism(w, &poly, base as f32, roof as f32, &proj, &proj, side)?;
+    }
+
+    writeln!(w, "}}")?;
+
+    if let Some(start) = Camera::start(level) {
+        let floor = level.zone_at(start.x, start.y).and_then(|z| level.zones.get(z as usize)).map_or(0, |z| z.floor as i32);
+        writeln!(w, "{{")?;
+        writeln!(w, "\"classname\" \"info_player_start\"")?;
+        writeln!(w, "\"origin\" \"{} {} {}\"", num(start.x), num(start.y), floor + PLAYER_ORIGIN_HEIGHT)?;
+        writeln!(w, "\"angle\" \"{}\"", start.angle.round() as i32)?;
+        writeln!(w, "}}")?;
+    }
+
+    for o in level.objects.iter() {
+        writeln!(w, "{{")?;
+        writeln!(w, "\"classname\" \"info_notnull\"")?;
+        writeln!(w, "\"origin\" \"{} {} {}\"", o.x, o.y, o.z)?;
+        writeln!(w, "\"angle\" \"{}\"", o.angle as u32 * 360 / 65536)?;
+        writeln!(w, "\"kind\" \"{}\"", o.kind)?;
+        writeln!(w, "\"flags\" \"{}\"", o.flags)?;
+        writeln!(w, "}}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use gob::{Bitmap, Palette, PaletteDepth, RowOrder};
+    use gob::level::{Level, Vertex, Wall, Zone, NO_ZONE};
+    use super::{mip_align, miptex, num, write_map, MapTexture};
+
+    fn texture(size: u32) -> MapTexture {
+        let data = (0..size * size).map(|i| i as u8).collect::<Vec<_>>();
+        let bitmap = Bitmap::new(String::new(), size, size, 0, RowOrder::TopDown, data);
+        MapTexture { name: String::from("WALL"), bitmap, scale: (1.0, 1.0) }
+    }
+
+    #[test]
+    fn sizes_round_up_to_whole_mip_blocks() {
+        assert_eq!((mip_align(0), mip_align(1), mip_align(16), mip_align(17)), (16, 16, 16, 32));
+    }
+
+    #[test]
+    fn numbers_lose_trailing_zeros() {
+        assert_eq!((num(1.0), num(0.5), num(-0.00001), num(100.0), num(1.23456)),
+                   (String::from("1"), String::from("0.5"), String::from("0"), String::from("100"), String::from("1.2346")));
+    }
+
+    #[test]
+    fn miptex_offsets_point_at_each_mip_level() {
+        let palette = Palette { r: [0; 256], g: [0; 256], b: [0; 256], depth: PaletteDepth::Rgb8 };
+        let lump = miptex(&texture(16), &palette).unwrap();
+
+        assert_eq!(&lump[..5], b"WALL\0");
+        let field = |i: usize| LittleEndian::read_u32(&lump[16 + 4 * i..]) as usize;
+        assert_eq!((field(0), field(1)), (16, 16));
+        assert_eq!((field(2), field(3), field(4), field(5)), (40, 40 + 256, 40 + 256 + 64, 40 + 256 + 64 + 16));
+
+        // Each level halves the one before, sampling every other pixel
+        assert_eq!(&lump[40..43], &[0, 1, 2]);
+        assert_eq!(&lump[296..299], &[0, 2, 4]);
+        assert_eq!(lump[296 + 8], 32);
+        assert_eq!(&lump[380..382], &[0, 1]);
+        assert_eq!(lump.len(), 1152);
+    }
+
+    #[test]
+    fn square_zone_becomes_slabs_and_wall_brushes() {
+        let wall = |v1, v2| Wall { v1, v2, front: 0, back: NO_ZONE, texture: 0, flags: 0 };
+        let level = Level {
+            name: String::from("E1L1"), rebuild_zone: 0,
+            vertices: vec![Vertex { x: 0, y: 0 }, Vertex { x: 0, y: 64 }, Vertex { x: 64, y: 64 }, Vertex { x: 64, y: 0 }],
+            walls: vec![wall(0, 1), wall(1, 2), wall(2, 3), wall(3, 0)],
+            zones: vec![Zone { floor: 0, ceiling: 128, floor_texture: 0, ceiling_texture: 0, light: 0, flags: 0 }],
+            objects: Vec::new(), header_extra: Vec::new(), extra: Vec::new()
+        };
+
+        let mut out = Vec::new();
+        write_map(&level, &[texture(16)], None, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        // A floor and a ceiling slab, and a brush behind each wall, six faces apiece
+        assert_eq!(text.lines().filter(|l| *l == "{").count(), 1 + 6 + 1);
+        assert_eq!(text.lines().filter(|l| l.starts_with('(')).count(), 6 * 6);
+        assert!(text.contains("( 0 0 0 ) ( 0 1 0 ) ( 1 0 0 ) WALL [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1\n"), "{}", text);
+        assert!(text.contains("( 0 0 -16 ) ( 1 0 -16 ) ( 0 1 -16 ) WALL"), "{}", text);
+        assert!(text.contains("\"origin\" \"32 32 24\""), "{}", text);
+    }
+}
@@ -0,0 +1,406 @@
+use std::io;
+use std::ops::Range;
+
+use gob::{clean_name, Bitmap, Level, RowOrder};
+use gob::level::{Zone, NO_ZONE};
+use gob::link::{self, LinkedTexture};
+use gob::map::Gob;
+use gob::shade::ShadeTable;
+
+/// Most zones a single ray will pass through before giving up, in case of a loop of
+/// two-sided walls that never reaches a solid one.
+const MAX_PORTALS: usize = 256;
+
+/// Distance over which the light drops by one shade level.
+const SHADE_DISTANCE: f32 = 256.0;
+
+/// Where the view is rendered from. `angle` is in degrees, counter-clockwise from the +x
+/// axis like an object's; `fov` is the horizontal field of view in degrees.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub angle: f32,
+    pub fov: f32
+}
+
+impl Camera {
+    /// A camera at (x, y) facing `angle`, at eye height in whatever zone it's in.
+    pub fn at(level: &Level, x: f32, y: f32, angle: f32) -> Camera {
+        let z = match level.zone_at(x, y).and_then(|z| level.zones.get(z as usize)) {
+            Some(zone) => eye_height(zone),
+            None => 0.0
+        };
+
+        Camera { x, y, z, angle, fov: 90.0 }
+    }
+
+    /// A default view of the level: from the first object standing in the rebuild zone,
+    /// facing the way it does, or else from the middle of the rebuild zone facing +x.
+    pub fn start(level: &Level) -> Option<Camera> {
+        let zone = level.rebuild_zone;
+
+        for o in level.objects.iter() {
+            if level.zone_at(o.x as f32, o.y as f32) == Some(zone) {
+                return Some(Camera::at(level, o.x as f32, o.y as f32, o.angle as f32 * 360.0 / 65536.0));
+            }
+        }
+
+        level.zone_middle(zone).map(|(x, y)| Camera::at(level, x, y, 0.0))
+    }
+}
+
+/// Halfway between the zone's floor and ceiling.
+fn eye_height(zone: &Zone) -> f32 {
+    (zone.floor as f32 + zone.ceiling as f32) / 2.0
+}
+
+struct Surface<'a> {
+    bitmap: Bitmap<'a>,
+    pixel_size: f32
+}
+
+/// Draws levels in 8 bit with their own textures, much as the game does: walls, floors and
+/// ceilings are textured in palette indices and darkened through the shade table by each
+/// zone's light level and by distance.
+///
+/// Each screen column casts a ray that walks from the camera's zone through two-sided
+/// walls until it hits a solid one, drawing the floor and ceiling of every zone it
+/// crosses and the steps between them.
+pub struct Renderer<'a> {
+    textures: Vec<Surface<'a>>,
+    missing: Surface<'a>,
+    shade: &'a ShadeTable
+}
+
+impl<'a> Renderer<'a> {
+    /// Loads every texture's bitmap, standing in a placeholder for any that dangle.
+    pub fn new(gob: &'a Gob, textures: &[LinkedTexture], shade: &'a ShadeTable) -> Result<Renderer<'a>, io::Error> {
+        let mut surfaces = Vec::with_capacity(textures.len());
+        for t in textures.iter() {
+            let bitmap = match t.bitmap {
+                Some(h) => gob.bitmap(h.index())?,
+                None => link::placeholder_bitmap(&t.texture.name)
+            };
+            surfaces.push(Surface { bitmap, pixel_size: t.texture.pixel_size });
+        }
+
+        Ok(Renderer { textures: surfaces, missing: Surface { bitmap: link::placeholder_bitmap(""), pixel_size: 1.0 },
+                      shade })
+    }
+
+    fn texture(&self, idx: u16) -> &Surface<'a> {
+        self.textures.get(idx as usize).unwrap_or(&self.missing)
+    }
+
+    /// Renders the level as seen from `camera`, as a bitmap of palette indices. Returns
+    /// `None` if the camera isn't inside any zone.
+    pub fn render(&self, level: &Level, camera: &Camera, width: u32, height: u32) -> Option<Bitmap<'static>> {
+        let start = match level.zone_at(camera.x, camera.y) {
+            Some(z) if (z as usize) < level.zones.len() => z,
+            _ => return None
+        };
+
+        let mut zone_walls = vec![Vec::new(); level.zones.len()];
+        for (i, w) in level.walls.iter().enumerate() {
+            for &z in [w.front, w.back].iter() {
+                if let Some(walls) = zone_walls.get_mut(z as usize) {
+                    walls.push(i);
+                }
+            }
+        }
+
+        let mut frame = Frame {
+            width, height, data: vec![0u8; (width * height) as usize],
+            focal: width as f32 / 2.0 / (camera.fov.to_radians() / 2.0).tan(),
+            camera: *camera
+        };
+
+        let angle = camera.angle.to_radians();
+        let forward = (angle.cos(), angle.sin());
+        let right = (angle.sin(), -angle.cos());
+
+        for column in 0..width {
+            let k = (column as f32 + 0.5 - width as f32 / 2.0) / frame.focal;
+            let dir = (forward.0 + right.0 * k, forward.1 + right.1 * k);
+            self.cast(level, &zone_walls, &mut frame, start, column, dir);
+        }
+
+        Some(Bitmap::new(String::from(clean_name(&level.name)), width, height, 0, RowOrder::TopDown, frame.data))
+    }
+
+    /// Follows one column's ray from zone to zone. `dir` is scaled so that the distance
+    /// along it is the depth into the screen.
+    fn cast(&self, level: &Level, zone_walls: &[Vec<usize>], frame: &mut Frame, start: u16, column: u32, dir: (f32, f32)) {
+        let mut zone_idx = start;
+        let mut top = 0i32;
+        let mut bottom = frame.height as i32;
+        let mut near = 0.0f32;
+
+        for _ in 0..MAX_PORTALS {
+            let zone = &level.zones[zone_idx as usize];
+
+            // Nearest wall of this zone beyond the one the ray came in through
+            let mut hit = None;
+            for &i in zone_walls[zone_idx as usize].iter() {
+                let w = &level.walls[i];
+                let (a, b) = match (level.vertex(w.v1), level.vertex(w.v2)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue
+                };
+                if let Some((t, s)) = intersect(frame.camera, dir, (a.x as f32, a.y as f32), (b.x as f32, b.y as f32)) {
+                    if t > near + 1e-3 && hit.is_none_or(|(best, _, _)| t < best) {
+                        let length = ((b.x as f32 - a.x as f32).powi(2) + (b.y as f32 - a.y as f32).powi(2)).sqrt();
+                        hit = Some((t, i, s * length));
+                    }
+                }
+            }
+
+            let (t, wall_idx, along) = match hit {
+                Some(hit) => hit,
+                None => {
+                    self.draw_plane(frame, zone, zone.ceiling, zone.ceiling_texture, dir, Span { column, rows: top..bottom });
+                    self.draw_plane(frame, zone, zone.floor, zone.floor_texture, dir, Span { column, rows: top..bottom });
+                    return;
+                }
+            };
+
+            let ceiling_y = frame.project(zone.ceiling, t).max(top).min(bottom);
+            let floor_y = frame.project(zone.floor, t).max(top).min(bottom);
+            self.draw_plane(frame, zone, zone.ceiling, zone.ceiling_texture, dir, Span { column, rows: top..ceiling_y });
+            self.draw_plane(frame, zone, zone.floor, zone.floor_texture, dir, Span { column, rows: floor_y..bottom });
+
+            let wall = &level.walls[wall_idx];
+            let other = if wall.front == zone_idx { wall.back } else { wall.front };
+            let next = match level.zones.get(other as usize) {
+                Some(next) if other != NO_ZONE => next,
+                _ => {
+                    self.draw_wall(frame, zone, wall.texture, zone.ceiling, (t, along), Span { column, rows: ceiling_y..floor_y });
+                    return;
+                }
+            };
+
+            let upper_y = frame.project(next.ceiling, t).max(ceiling_y).min(floor_y);
+            let lower_y = frame.project(next.floor, t).max(upper_y).min(floor_y);
+            self.draw_wall(frame, zone, wall.texture, zone.ceiling, (t, along), Span { column, rows: ceiling_y..upper_y });
+            self.draw_wall(frame, zone, wall.texture, next.floor, (t, along), Span { column, rows: lower_y..floor_y });
+
+            top = upper_y;
+            bottom = lower_y;
+            if top >= bottom {
+                return;
+            }
+
+            zone_idx = other;
+            near = t;
+        }
+    }
+
+    /// Fills a span of a column with a floor or ceiling at height `z`.
+    fn draw_plane(&self, frame: &mut Frame, zone: &Zone, z: i16, texture: u16, dir: (f32, f32), span: Span) {
+        let surface = self.texture(texture);
+        let cam = frame.camera;
+
+        for row in span.rows.start.max(0)..span.rows.end.min(frame.height as i32) {
+            let dy = frame.height as f32 / 2.0 - (row as f32 + 0.5);
+            let depth = (z as f32 - cam.z) * frame.focal / dy;
+            if depth.is_nan() || depth <= 0.0 {
+                continue;
+            }
+
+            let x = cam.x + dir.0 * depth;
+            let y = cam.y + dir.1 * depth;
+            let p = sample(surface, x, -y);
+            frame.set(span.column, row, self.shade(p, zone, depth));
+        }
+    }
+
+    /// Fills a span of a column with a wall `t` away, `along` units from its start, given
+    /// as `(t, along)`. The texture is pinned to the height `anchor`.
+    fn draw_wall(&self, frame: &mut Frame, zone: &Zone, texture: u16, anchor: i16, (t, along): (f32, f32), span: Span) {
+        let surface = self.texture(texture);
+        let cam = frame.camera;
+
+        for row in span.rows.start.max(0)..span.rows.end.min(frame.height as i32) {
+            let dy = frame.height as f32 / 2.0 - (row as f32 + 0.5);
+            let z = cam.z + dy * t / frame.focal;
+            let p = sample(surface, along, anchor as f32 - z);
+            frame.set(span.column, row, self.shade(p, zone, t));
+        }
+    }
+
+    /// Darkens a palette index by the zone's light level plus one level every
+    /// `SHADE_DISTANCE` units away.
+    fn shade(&self, p: u8, zone: &Zone, depth: f32) -> u8 {
+        let count = self.shade.level_count();
+        if count == 0 {
+            return p;
+        }
+
+        let level = zone.light as usize + (depth / SHADE_DISTANCE) as usize;
+        self.shade.levels[level.min(count - 1)][p as usize]
+    }
+}
+
+/// The column being drawn into and the projection it's drawn with.
+struct Frame {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    focal: f32,
+    camera: Camera
+}
+
+impl Frame {
+    /// Screen row at which height `z` appears, `depth` units into the screen.
+    fn project(&self, z: i16, depth: f32) -> i32 {
+        (self.height as f32 / 2.0 - (z as f32 - self.camera.z) * self.focal / depth).ceil() as i32
+    }
+
+    fn set(&mut self, x: u32, y: i32, p: u8) {
+        self.data[(y as u32 * self.width + x) as usize] = p;
+    }
+}
+
+/// The rows of one screen column to fill.
+struct Span {
+    column: u32,
+    rows: Range<i32>
+}
+
+/// Where a ray from the camera crosses the segment a-b, as the distance along the ray and
+/// the fraction of the way from a to b.
+fn intersect(cam: Camera, dir: (f32, f32), a: (f32, f32), b: (f32, f32)) -> Option<(f32, f32)> {
+    let edge = (b.0 - a.0, b.1 - a.1);
+    let denom = dir.0 * edge.1 - dir.1 * edge.0;
+    if denom.abs() < 1e-9 {
+        return None;
+    }
+
+    let (dx, dy) = (a.0 - cam.x, a.1 - cam.y);
+    let t = (dx * edge.1 - dy * edge.0) / denom;
+    let s = (dx * dir.1 - dy * dir.0) / denom;
+    if !(0.0..=1.0).contains(&s) {
+        return None;
+    }
+
+    Some((t, s))
+}
+
+/// Texel at world units (u, v), tiling the texture at its pixel size.
+fn sample(surface: &Surface, u: f32, v: f32) -> u8 {
+    let size = if surface.pixel_size > 0.0 && surface.pixel_size.is_finite() { surface.pixel_size } else { 1.0 };
+    let bmp = &surface.bitmap;
+    if bmp.width == 0 || bmp.height == 0 {
+        return 0;
+    }
+    let x = wrap((u / size).floor() as i64, bmp.width);
+    let y = wrap((v / size).floor() as i64, bmp.height);
+
+    bmp.pixel(x, y)
+}
+
+fn wrap(i: i64, n: u32) -> u32 {
+    (((i % n as i64) + n as i64) % n as i64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use gob::{Bitmap, Level, RowOrder};
+    use gob::level::{Vertex, Wall, Zone, NO_ZONE};
+    use gob::shade::ShadeTable;
+    use super::{intersect, Camera, Frame, Renderer, Surface, SHADE_DISTANCE};
+
+    /// A 256 unit square room, 64 high, with ceiling texture 0, floor texture 1 and wall
+    /// texture 2.
+    fn room(light: u16) -> Level {
+        let corners = [(0, 0), (0, 256), (256, 256), (256, 0)];
+        Level {
+            name: String::from("ROOM"),
+            rebuild_zone: 0,
+            vertices: corners.iter().map(|&(x, y)| Vertex { x, y }).collect(),
+            walls: (0..4).map(|i| Wall { v1: i, v2: (i + 1) % 4, front: 0, back: NO_ZONE, texture: 2, flags: 0 }).collect(),
+            zones: vec![Zone { floor: 0, ceiling: 64, floor_texture: 1, ceiling_texture: 0, light, flags: 0 }],
+            objects: Vec::new(),
+            header_extra: Vec::new(),
+            extra: Vec::new()
+        }
+    }
+
+    /// Solid textures of colours 1, 2 and 3, and four shade levels that each add 10.
+    fn renderer(shade: &ShadeTable) -> Renderer<'_> {
+        let solid = |p: u8| Surface { bitmap: Bitmap::new(String::new(), 1, 1, 0, RowOrder::TopDown, vec![p]), pixel_size: 1.0 };
+        Renderer { textures: vec![solid(1), solid(2), solid(3)], missing: solid(0), shade }
+    }
+
+    fn shade_table() -> ShadeTable {
+        let mut levels = vec![[0u8; 256]; 4];
+        for (l, level) in levels.iter_mut().enumerate() {
+            for (p, out) in level.iter_mut().enumerate() {
+                *out = (p + 10 * l) as u8;
+            }
+        }
+        ShadeTable { levels }
+    }
+
+    fn camera(x: f32, y: f32, z: f32) -> Camera {
+        Camera { x, y, z, angle: 0.0, fov: 90.0 }
+    }
+
+    #[test]
+    fn projects_heights_onto_rows() {
+        let frame = Frame { width: 1, height: 100, data: Vec::new(), focal: 50.0, camera: camera(0.0, 0.0, 0.0) };
+        assert_eq!(frame.project(10, 50.0), 40);
+        assert_eq!(frame.project(-10, 50.0), 60);
+        assert_eq!(frame.project(10, 100.0), 45);
+    }
+
+    #[test]
+    fn intersects_rays_with_walls() {
+        let cam = camera(0.0, 0.0, 0.0);
+        assert_eq!(intersect(cam, (1.0, 0.0), (10.0, -5.0), (10.0, 15.0)), Some((10.0, 0.25)));
+        assert_eq!(intersect(cam, (1.0, 0.0), (10.0, 5.0), (10.0, 15.0)), None);
+        assert_eq!(intersect(cam, (1.0, 0.0), (0.0, 5.0), (10.0, 5.0)), None);
+    }
+
+    #[test]
+    fn draws_ceiling_wall_and_floor() {
+        let shade = shade_table();
+        let r = renderer(&shade);
+
+        // With a 90 degree view three columns wide, the middle one looks straight down +x
+        // at the far wall 128 units away, which projects onto row 50.
+        let column = |light: u16| {
+            let frame = r.render(&room(light), &camera(128.0, 128.0, 32.0), 3, 100).unwrap();
+            (0..100).map(|y| frame.pixel(1, y)).collect::<Vec<_>>()
+        };
+
+        let dark = column(0);
+        assert!(dark[..50].iter().all(|&p| p == 1));
+        assert_eq!(dark[50], 3);
+        assert!(dark[51..].iter().all(|&p| p == 2));
+
+        let lit = column(1);
+        assert_eq!((lit[0], lit[50], lit[99]), (11, 13, 12));
+    }
+
+    #[test]
+    fn shades_by_light_and_distance() {
+        let shade = shade_table();
+        let r = renderer(&shade);
+        let zone = |light| Zone { floor: 0, ceiling: 64, floor_texture: 0, ceiling_texture: 0, light, flags: 0 };
+
+        assert_eq!(r.shade(5, &zone(0), 0.0), 5);
+        assert_eq!(r.shade(5, &zone(1), SHADE_DISTANCE - 1.0), 15);
+        assert_eq!(r.shade(5, &zone(1), SHADE_DISTANCE * 2.0), 35);
+        assert_eq!(r.shade(5, &zone(3), SHADE_DISTANCE * 10.0), 35);
+        assert_eq!(renderer(&ShadeTable { levels: Vec::new() }).shade(5, &zone(3), 0.0), 5);
+    }
+
+    #[test]
+    fn nothing_to_draw_outside_the_level() {
+        let shade = shade_table();
+        assert!(renderer(&shade).render(&room(0), &camera(300.0, 128.0, 32.0), 3, 100).is_none());
+    }
+}
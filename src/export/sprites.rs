@@ -0,0 +1,248 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use gif;
+use serde_json;
+
+use gob::{clean_name, file_stem, Bitmap, Palette};
+use gob::link::LinkedTexture;
+use gob::map::Gob;
+use export::image::write_indexed_png;
+
+/// Sheets wrap onto a new row once a row would grow past this width.
+const SHEET_MAX_WIDTH: u32 = 512;
+
+/// Gap left between frames on a sheet.
+const SHEET_PADDING: u32 = 1;
+
+/// Palette index treated as transparent in sheets and previews.
+const TRANSPARENT_INDEX: u8 = 0;
+
+/// Delay between preview frames, in hundredths of a second.
+const GIF_FRAME_DELAY: u16 = 10;
+
+pub struct SpriteFrame<'a> {
+    pub bitmap_idx: usize,
+    pub bitmap: Bitmap<'a>
+}
+
+/// Bitmaps sharing a name prefix, e.g. `SPRA0`, `SPRA1` and `SPRA2`, in frame number order.
+/// `file` is the name safe to save it under: `name` with anything unsafe replaced, or
+/// `sprite_<n>` for the first frame's bitmap if that leaves nothing, with `_<n>` added to
+/// any already taken.
+pub struct Sequence<'a> {
+    pub name: String,
+    pub file: String,
+    pub frames: Vec<SpriteFrame<'a>>
+}
+
+#[derive(Serialize)]
+pub struct FrameRect {
+    pub name: String,
+    pub bitmap: usize,
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32
+}
+
+/// A packed sprite sheet, as palette indices.
+pub struct Sheet {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+    pub frames: Vec<FrameRect>
+}
+
+#[derive(Serialize)]
+struct SheetMap<'a> {
+    image: String,
+    width: u32,
+    height: u32,
+    frames: &'a [FrameRect]
+}
+
+/// Bitmaps that aren't used by any texture, plus any whose flags overlap `flag_mask`.
+pub fn find_sprites(gob: &Gob, textures: &[LinkedTexture], flag_mask: u16) -> Result<Vec<usize>, io::Error> {
+    let mut used = vec![false; gob.index.bitmap_count()];
+    for t in textures {
+        if let Some(h) = t.bitmap {
+            used[h.index()] = true;
+        }
+    }
+
+    let mut sprites = Vec::new();
+    for (i, &used) in used.iter().enumerate() {
+        if !used || gob.bitmap_header(i)?.flags & flag_mask != 0 {
+            sprites.push(i);
+        }
+    }
+
+    Ok(sprites)
+}
+
+/// Name prefix a bitmap is grouped under: its name with trailing frame digits removed.
+pub fn sequence_name(name: &str) -> String {
+    let name = clean_name(name);
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+
+    if prefix.is_empty() { String::from(name) } else { String::from(prefix) }
+}
+
+/// Sort key putting frames in order by number, so `SPR2` comes before `SPR10`, with the
+/// name itself breaking ties like `SPR01` and `SPR1`.
+fn frame_key(name: &str) -> (String, u64, String) {
+    let name = clean_name(name);
+    let digits = &name[name.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+    let number = if digits.is_empty() { 0 } else { digits.parse().unwrap_or(u64::MAX) };
+
+    (sequence_name(name), number, String::from(name))
+}
+
+/// The file name for a sequence, given those of the sequences before it.
+fn sequence_file(name: &str, first_bitmap: usize, taken: &[String]) -> String {
+    let mut file = file_stem(name);
+    if file.is_empty() {
+        file = format!("sprite_{}", first_bitmap);
+    }
+    if taken.contains(&file) {
+        file = format!("{}_{}", file, taken.len());
+    }
+
+    file
+}
+
+pub fn group_sequences<'a>(gob: &'a Gob, bitmaps: &[usize]) -> Result<Vec<Sequence<'a>>, io::Error> {
+    let mut sequences: Vec<Sequence> = Vec::new();
+
+    for &i in bitmaps {
+        let bmp = gob.bitmap(i)?;
+        let name = sequence_name(&bmp.name);
+        let frame = SpriteFrame { bitmap_idx: i, bitmap: bmp };
+
+        match sequences.iter().position(|s| s.name == name) {
+            Some(pos) => sequences[pos].frames.push(frame),
+            None => sequences.push(Sequence { name, file: String::new(), frames: vec![frame] })
+        }
+    }
+
+    let mut files = Vec::new();
+    for s in sequences.iter_mut() {
+        s.frames.sort_by_key(|f| frame_key(&f.bitmap.name));
+        s.file = sequence_file(&s.name, s.frames[0].bitmap_idx, &files);
+        files.push(s.file.clone());
+    }
+
+    Ok(sequences)
+}
+
+/// Packs a sequence's frames left to right in rows.
+pub fn pack(seq: &Sequence) -> Sheet {
+    let mut frames = Vec::new();
+    let (mut x, mut y, mut row_height, mut width) = (0, 0, 0, 0);
+
+    for f in seq.frames.iter() {
+        let (w, h) = (f.bitmap.width, f.bitmap.height);
+        if x > 0 && x + w > SHEET_MAX_WIDTH {
+            x = 0;
+            y += row_height + SHEET_PADDING;
+            row_height = 0;
+        }
+
+        frames.push(FrameRect { name: String::from(clean_name(&f.bitmap.name)), bitmap: f.bitmap_idx, x, y, w, h });
+        x += w + SHEET_PADDING;
+        width = width.max(x - SHEET_PADDING);
+        row_height = row_height.max(h);
+    }
+    let height = y + row_height;
+
+    let mut data = vec![TRANSPARENT_INDEX; (width * height) as usize];
+    for (f, rect) in seq.frames.iter().zip(frames.iter()) {
+        for row in 0..rect.h {
+            let src = f.bitmap.row(row);
+            let dst = ((rect.y + row) * width + rect.x) as usize;
+            data[dst..dst + rect.w as usize].copy_from_slice(src);
+        }
+    }
+
+    Sheet { width, height, data, frames }
+}
+
+/// Writes `<file>.png` and a `<file>.json` frame map for a sequence into `dir`.
+pub fn write_sheet(seq: &Sequence, palette: &Palette, dir: &Path) -> Result<(), io::Error> {
+    let sheet = pack(seq);
+    let image = format!("{}.png", seq.file);
+
+    write_indexed_png(BufWriter::new(File::create(dir.join(&image))?), sheet.width, sheet.height,
+                      palette, &sheet.data, Some(TRANSPARENT_INDEX))?;
+
+    let map = SheetMap { image, width: sheet.width, height: sheet.height, frames: &sheet.frames };
+    serde_json::to_writer_pretty(BufWriter::new(File::create(dir.join(format!("{}.json", seq.file)))?), &map)?;
+
+    Ok(())
+}
+
+/// Writes the sequence as a looping GIF, each frame centred on a canvas big enough for all of them.
+pub fn write_gif(seq: &Sequence, palette: &Palette, path: &Path) -> Result<(), io::Error> {
+    let width = seq.frames.iter().map(|f| f.bitmap.width).max().unwrap_or(1);
+    let height = seq.frames.iter().map(|f| f.bitmap.height).max().unwrap_or(1);
+
+    let mut encoder = gif::Encoder::new(BufWriter::new(File::create(path)?), width as u16, height as u16, &palette.to_rgb())
+        .map_err(gif_error)?;
+    encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+
+    for f in seq.frames.iter() {
+        let (ox, oy) = ((width - f.bitmap.width) / 2, (height - f.bitmap.height) / 2);
+        let mut data = vec![TRANSPARENT_INDEX; (width * height) as usize];
+        for y in 0..f.bitmap.height {
+            for x in 0..f.bitmap.width {
+                data[((oy + y) * width + ox + x) as usize] = f.bitmap.pixel(x, y);
+            }
+        }
+
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            delay: GIF_FRAME_DELAY,
+            transparent: Some(TRANSPARENT_INDEX),
+            dispose: gif::DisposalMethod::Background,
+            buffer: Cow::Owned(data),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(gif_error)?;
+    }
+
+    Ok(())
+}
+
+fn gif_error(err: gif::EncodingError) -> io::Error {
+    match err {
+        gif::EncodingError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frame_key, sequence_file, sequence_name};
+
+    #[test]
+    fn frames_sort_by_number() {
+        let mut names = vec!["SPR10", "SPR2", "SPR1\0\0\0", "SPR01"];
+        names.sort_by_key(|n| frame_key(n));
+        assert_eq!(names, ["SPR01", "SPR1\0\0\0", "SPR2", "SPR10"]);
+        assert_eq!(sequence_name("SPR10"), "SPR");
+    }
+
+    #[test]
+    fn file_names_are_safe_and_distinct() {
+        let taken = vec![String::from("SPR"), String::from("A_B")];
+        assert_eq!(sequence_file("SPR", 3, &[]), "SPR");
+        assert_eq!(sequence_file("../x", 3, &[]), "___x");
+        assert_eq!(sequence_file("A/B", 3, &taken), "A_B_2");
+        assert_eq!(sequence_file("", 7, &taken), "sprite_7");
+    }
+}
@@ -0,0 +1,110 @@
+use std::io;
+use std::io::Write;
+
+use gob::clean_name;
+use gob::level::{Level, NO_ZONE};
+
+/// Width the map is drawn at; the height follows the level's aspect ratio.
+const SVG_WIDTH: f32 = 1024.0;
+
+/// Fill colour for a zone, spreading ids around the colour wheel so neighbours differ.
+fn zone_colour(zone: usize) -> String {
+    format!("hsl({:.0},60%,75%)", (zone as f32 * 137.508) % 360.0)
+}
+
+/// Largest 1, 2 or 5 times a power of ten no longer than `max`.
+fn nice_length(max: f32) -> f32 {
+    let mut step = 10f32.powf(max.log10().floor());
+    for &m in [5.0, 2.0, 1.0].iter() {
+        if step * m <= max {
+            step *= m;
+            break;
+        }
+    }
+
+    step
+}
+
+/// Draws a level top down: zones filled by id with the rebuild zone outlined in red, solid
+/// walls black and two-sided walls grey, objects as labelled markers pointing along their
+/// angle, and a scale bar in map units.
+pub fn write_level_svg<W: Write>(level: &Level, w: &mut W) -> Result<(), io::Error> {
+    let ((min_x, min_y), (max_x, max_y)) = level.bounds().unwrap_or(((0, 0), (1, 1)));
+    let span = ((max_x as f32 - min_x as f32).max(max_y as f32 - min_y as f32)).max(1.0);
+    let margin = span * 0.05;
+    let (left, top) = (min_x as f32 - margin, -(max_y as f32) - margin);
+    let (vw, vh) = (max_x as f32 - min_x as f32 + margin * 2.0, max_y as f32 - min_y as f32 + margin * 4.0);
+    let stroke = span / 400.0;
+
+    // Map y points up, SVG y points down
+    let px = |x: i16| x as f32;
+    let py = |y: i16| 0.0 - y as f32;
+
+    writeln!(w, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"{} {} {} {}\">",
+             SVG_WIDTH, SVG_WIDTH * vh / vw, left, top, vw, vh)?;
+    writeln!(w, "<title>{}</title>", escape(clean_name(&level.name)))?;
+    writeln!(w, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\"/>", left, top, vw, vh)?;
+
+    writeln!(w, "<g id=\"zones\">")?;
+    for z in 0..level.zones.len() {
+        let (loops, _) = level.zone_loops(z as u16);
+        let mut d = String::new();
+        for outline in loops.iter() {
+            for (i, &v) in outline.iter().enumerate() {
+                if let Some(v) = level.vertex(v) {
+                    d.push_str(&format!("{}{} {} ", if i == 0 { "M" } else { "L" }, px(v.x), py(v.y)));
+                }
+            }
+            d.push_str("Z ");
+        }
+
+        if z as u16 == level.rebuild_zone {
+            writeln!(w, "<path d=\"{}\" fill=\"{}\" fill-rule=\"evenodd\" stroke=\"red\" stroke-width=\"{}\"><title>zone {} (rebuild)</title></path>",
+                     d.trim(), zone_colour(z), stroke * 4.0, z)?;
+        } else {
+            writeln!(w, "<path d=\"{}\" fill=\"{}\" fill-rule=\"evenodd\"><title>zone {}</title></path>", d.trim(), zone_colour(z), z)?;
+        }
+    }
+    writeln!(w, "</g>")?;
+
+    writeln!(w, "<g id=\"walls\" stroke-linecap=\"round\">")?;
+    for (i, wall) in level.walls.iter().enumerate() {
+        let (a, b) = match (level.vertex(wall.v1), level.vertex(wall.v2)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue
+        };
+        let (colour, width) = if wall.back == NO_ZONE { ("black", stroke * 2.0) } else { ("grey", stroke) };
+        writeln!(w, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"><title>wall {}</title></line>",
+                 px(a.x), py(a.y), px(b.x), py(b.y), colour, width, i)?;
+    }
+    writeln!(w, "</g>")?;
+
+    let r = span / 150.0;
+    writeln!(w, "<g id=\"objects\" font-family=\"sans-serif\" font-size=\"{}\">", r * 2.0)?;
+    for (i, o) in level.objects.iter().enumerate() {
+        let (x, y) = (px(o.x), py(o.y));
+        let a = o.angle as f32 / 65536.0 * 2.0 * ::std::f32::consts::PI;
+        writeln!(w, "<g><title>object {} (kind {})</title>", i, o.kind)?;
+        writeln!(w, "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"blue\"/>", x, y, r)?;
+        writeln!(w, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"blue\" stroke-width=\"{}\"/>",
+                 x, y, x + a.cos() * r * 2.0, y - a.sin() * r * 2.0, stroke)?;
+        writeln!(w, "<text x=\"{}\" y=\"{}\">{}</text>", x + r * 1.5, y - r * 1.5, o.kind)?;
+        writeln!(w, "</g>")?;
+    }
+    writeln!(w, "</g>")?;
+
+    let bar = nice_length(span / 4.0);
+    let (bx, by) = (left + margin, py(min_y) + margin * 2.0);
+    writeln!(w, "<g id=\"scale\" font-family=\"sans-serif\" font-size=\"{}\">", margin * 0.6)?;
+    writeln!(w, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\" stroke-width=\"{}\"/>", bx, by, bx + bar, by, stroke * 2.0)?;
+    writeln!(w, "<text x=\"{}\" y=\"{}\">{} units</text>", bx, by - margin * 0.3, bar)?;
+    writeln!(w, "</g>")?;
+
+    writeln!(w, "</svg>")?;
+
+    Ok(())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
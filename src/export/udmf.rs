@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use gob::{clean_name, Bitmap, Level, Palette};
+use gob::level::NO_ZONE;
+use gob::link::{self, LinkedTexture};
+use gob::trigger::{not_a_trigger_list, Action, TriggerSource};
+use gob::map::Gob;
+use export::render::Camera;
+
+/// Doom flats are always 64x64.
+const FLAT_SIZE: u32 = 64;
+
+/// Longest run of rows in one patch post. Keeping every post this long lets textures taller
+/// than 254 rows use the tall patch convention: a post whose top delta is no greater than
+/// the one before it is taken relative to it.
+const POST_LENGTH: u32 = 128;
+
+/// Doom light levels per shade table level, so level 0 is 255 and level 15 is 15.
+const LIGHT_STEP: i32 = 16;
+
+/// Doom editor number of the player 1 start.
+const PLAYER_START: u16 = 1;
+
+/// ZDoom's map spot, an invisible marker. Objects are written as these, with their kind
+/// and flags kept as user fields, since what they should become is up to the port.
+const MAP_SPOT: u16 = 9001;
+
+/// Hexen-style specials for walls that open a door or lower a lift: Door_Raise(tag, speed,
+/// delay) and Plat_DownWaitUpStay(tag, speed, delay). Both move to the usual Doom heights
+/// rather than the trigger's own, which UDMF has no way to give.
+const DOOR_RAISE: u16 = 12;
+const PLAT_DOWN_WAIT_UP_STAY: u16 = 62;
+
+/// Things appear on every skill and in every game mode.
+const THING_FLAGS: &str = "skill1 = true; skill2 = true; skill3 = true; skill4 = true; skill5 = true; single = true; coop = true; dm = true;";
+
+pub struct Lump {
+    pub name: String,
+    pub data: Vec<u8>
+}
+
+impl Lump {
+    fn new(name: &str, data: Vec<u8>) -> Lump {
+        Lump { name: String::from(name), data }
+    }
+}
+
+/// Writes a PWAD: the header, the lumps back to back and then the directory.
+pub fn write_pwad<W: Write>(w: &mut W, lumps: &[Lump]) -> Result<(), io::Error> {
+    let data_size: usize = lumps.iter().map(|l| l.data.len()).sum();
+    w.write_all(b"PWAD")?;
+    w.write_i32::<LittleEndian>(lumps.len() as i32)?;
+    w.write_i32::<LittleEndian>(12 + data_size as i32)?;
+
+    for l in lumps.iter() {
+        w.write_all(&l.data)?;
+    }
+
+    let mut pos = 12;
+    for l in lumps.iter() {
+        w.write_i32::<LittleEndian>(pos)?;
+        w.write_i32::<LittleEndian>(l.data.len() as i32)?;
+        let mut name = [0u8; 8];
+        for (i, b) in l.name.bytes().take(8).enumerate() {
+            name[i] = b;
+        }
+        w.write_all(&name)?;
+        pos += l.data.len() as i32;
+    }
+
+    Ok(())
+}
+
+/// Eight character lump names for every texture, upper case and unique, made from the
+/// texture names where possible.
+pub fn lump_names(textures: &[LinkedTexture]) -> Vec<String> {
+    let mut used = HashSet::new();
+    let mut names = Vec::with_capacity(textures.len());
+
+    for (i, t) in textures.iter().enumerate() {
+        let mut name: String = clean_name(&t.texture.name).chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .take(8)
+            .collect();
+        // A made up name can be some later texture's real one, or the other way round,
+        // so keep stepping until it's free
+        let mut n = i;
+        while name.is_empty() || used.contains(&name) {
+            name = format!("ACT{:05}", n);
+            n += textures.len();
+        }
+        used.insert(name.clone());
+        names.push(name);
+    }
+
+    names
+}
+
+/// Converts a bitmap to a Doom patch: column offsets, then each column as posts of
+/// palette indices.
+pub fn to_patch(bmp: &Bitmap) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.write_u16::<LittleEndian>(bmp.width as u16).unwrap();
+    out.write_u16::<LittleEndian>(bmp.height as u16).unwrap();
+    out.write_i16::<LittleEndian>(0).unwrap();
+    out.write_i16::<LittleEndian>(0).unwrap();
+
+    let mut columns = Vec::new();
+    let mut offsets = Vec::with_capacity(bmp.width as usize);
+    let header_size = 8 + 4 * bmp.width as usize;
+
+    for x in 0..bmp.width {
+        offsets.push((header_size + columns.len()) as u32);
+
+        let mut top = 0;
+        while top < bmp.height {
+            let length = (bmp.height - top).min(POST_LENGTH);
+            columns.push(if top == 0 { 0 } else { POST_LENGTH as u8 });
+            columns.push(length as u8);
+            columns.push(0);
+            for y in top..top + length {
+                columns.push(bmp.pixel(x, y));
+            }
+            columns.push(0);
+            top += length;
+        }
+        columns.push(0xff);
+    }
+
+    for o in offsets {
+        out.write_u32::<LittleEndian>(o).unwrap();
+    }
+    out.extend_from_slice(&columns);
+
+    out
+}
+
+/// Converts a bitmap to a Doom flat, resampling it to 64x64 if need be.
+pub fn to_flat(bmp: &Bitmap) -> Vec<u8> {
+    let mut out = Vec::with_capacity((FLAT_SIZE * FLAT_SIZE) as usize);
+    for y in 0..FLAT_SIZE {
+        for x in 0..FLAT_SIZE {
+            if bmp.width == 0 || bmp.height == 0 {
+                out.push(0);
+            } else {
+                out.push(bmp.pixel(x * bmp.width / FLAT_SIZE, y * bmp.height / FLAT_SIZE));
+            }
+        }
+    }
+
+    out
+}
+
+/// Builds a playable PWAD for one level: the palette as PLAYPAL, the textures its walls
+/// use as patches in the TX_ namespace, the ones its floors and ceilings use as flats, and
+/// the map itself as MAP01 in UDMF.
+pub fn level_pwad(gob: &Gob, level: &Level, textures: &[LinkedTexture], palette: &Palette) -> Result<Vec<Lump>, io::Error> {
+    let names = lump_names(textures);
+    let mut lumps = vec![Lump::new("PLAYPAL", palette.to_rgb())];
+
+    let mut walls: Vec<usize> = level.walls.iter().map(|w| w.texture as usize).filter(|&t| t < textures.len()).collect();
+    walls.sort();
+    walls.dedup();
+    let mut flats: Vec<usize> = level.zones.iter().flat_map(|z| vec![z.floor_texture as usize, z.ceiling_texture as usize])
+        .filter(|&t| t < textures.len())
+        .collect();
+    flats.sort();
+    flats.dedup();
+
+    let bitmap = |t: usize| -> Result<Bitmap, io::Error> {
+        match textures[t].bitmap {
+            Some(h) => gob.bitmap(h.index()),
+            None => Ok(link::placeholder_bitmap(&textures[t].texture.name))
+        }
+    };
+
+    lumps.push(Lump::new("TX_START", Vec::new()));
+    for &t in walls.iter() {
+        lumps.push(Lump::new(&names[t], to_patch(&bitmap(t)?)));
+    }
+    lumps.push(Lump::new("TX_END", Vec::new()));
+
+    lumps.push(Lump::new("FF_START", Vec::new()));
+    for &t in flats.iter() {
+        lumps.push(Lump::new(&names[t], to_flat(&bitmap(t)?)));
+    }
+    lumps.push(Lump::new("FF_END", Vec::new()));
+
+    let mut textmap = Vec::new();
+    write_textmap(level, &names, &mut textmap)?;
+    lumps.push(Lump::new("MAP01", Vec::new()));
+    lumps.push(Lump::new("TEXTMAP", textmap));
+    lumps.push(Lump::new("ENDMAP", Vec::new()));
+
+    Ok(lumps)
+}
+
+/// Where the player starts, as x, y and angle: the renderer's default view, or else the
+/// first object standing in any zone, or else the middle of the first zone that has one.
+fn player_start(level: &Level) -> Option<(f32, f32, f32)> {
+    if let Some(c) = Camera::start(level) {
+        return Some((c.x, c.y, c.angle));
+    }
+    if let Some(o) = level.objects.iter().find(|o| level.zone_at(o.x as f32, o.y as f32).is_some()) {
+        return Some((o.x as f32, o.y as f32, o.angle as f32 * 360.0 / 65536.0));
+    }
+
+    (0..level.zones.len() as u16).filter_map(|z| level.zone_middle(z)).next().map(|(x, y)| (x, y, 0.0))
+}
+
+/// Writes a level as a UDMF TEXTMAP in the ZDoom namespace. Zones become sectors and walls
+/// linedefs; `names` gives the lump name of each GOB texture. A wall whose front isn't a
+/// zone but whose back is is turned round to face it, and one with neither is left out
+/// with a warning, so linedefs keep the walls' indices only up to the first of those. Door
+/// and lift triggers set off by a wall become specials on its linedef, tagged with the
+/// target sector's index plus one; other triggers are left out. A level whose extra bytes
+/// aren't a trigger list as `Level::triggers` reads them gets no specials, with a warning.
+pub fn write_textmap<W: Write>(level: &Level, names: &[String], w: &mut W) -> Result<(), io::Error> {
+    let name = |t: u16| names.get(t as usize).map(|n| &n[..]).unwrap_or("-");
+
+    writeln!(w, "namespace = \"zdoom\";")?;
+    writeln!(w, "// {}", clean_name(&level.name))?;
+
+    match player_start(level) {
+        Some((x, y, angle)) => writeln!(w, "thing {{ x = {:.3}; y = {:.3}; angle = {}; type = {}; {} }}",
+                                        x, y, angle.round() as i32, PLAYER_START, THING_FLAGS)?,
+        None => eprintln!("warning: level {}: no zone to put the player start in", clean_name(&level.name))
+    }
+
+    for o in level.objects.iter() {
+        let floor = level.zone_at(o.x as f32, o.y as f32)
+            .and_then(|z| level.zones.get(z as usize))
+            .map_or(0, |z| z.floor);
+        writeln!(w, "thing {{ x = {}.0; y = {}.0; height = {}.0; angle = {}; type = {}; user_kind = {}; user_flags = {}; {} }}",
+                 o.x, o.y, o.z as i32 - floor as i32, (o.angle as u32 * 360 / 65536), MAP_SPOT, o.kind, o.flags, THING_FLAGS)?;
+    }
+
+    for v in level.vertices.iter() {
+        writeln!(w, "vertex {{ x = {}.0; y = {}.0; }}", v.x, v.y)?;
+    }
+
+    let triggers = level.triggers().unwrap_or_else(|| {
+        eprintln!("warning: level {}: {}, so it has no door or lift specials", clean_name(&level.name), not_a_trigger_list(level));
+        Vec::new()
+    });
+    let mut specials = HashMap::new();
+    for t in triggers.iter() {
+        let special = match t.action {
+            Action::Door => DOOR_RAISE,
+            Action::Lift => PLAT_DOWN_WAIT_UP_STAY,
+            Action::Other(_) => continue
+        };
+        if let TriggerSource::Wall(wall) = t.source {
+            specials.insert(wall as usize, format!(" special = {}; arg0 = {}; arg1 = {}; arg2 = {}; playeruse = true; repeatspecial = true;",
+                                                   special, t.target_zone as u32 + 1, t.speed, t.wait));
+        }
+    }
+    let tagged: HashSet<u16> = triggers.iter().map(|t| t.target_zone).collect();
+
+    let is_zone = |z: u16| z != NO_ZONE && (z as usize) < level.zones.len();
+    let mut sides = 0;
+    let mut sidedefs = Vec::new();
+    for (i, wall) in level.walls.iter().enumerate() {
+        let special = specials.get(&i).map_or("", |s| &s[..]);
+        let texture = name(wall.texture);
+        match (is_zone(wall.front), is_zone(wall.back)) {
+            (true, true) => {
+                writeln!(w, "linedef {{ v1 = {}; v2 = {}; sidefront = {}; sideback = {}; twosided = true;{} }}",
+                         wall.v1, wall.v2, sides, sides + 1, special)?;
+                for &zone in [wall.front, wall.back].iter() {
+                    sidedefs.push(format!("sidedef {{ sector = {}; texturetop = \"{}\"; texturebottom = \"{}\"; }}", zone, texture, texture));
+                }
+                sides += 2;
+            },
+            (front, back) if front || back => {
+                let (v1, v2, zone) = if front { (wall.v1, wall.v2, wall.front) } else { (wall.v2, wall.v1, wall.back) };
+                writeln!(w, "linedef {{ v1 = {}; v2 = {}; sidefront = {}; blocking = true;{} }}", v1, v2, sides, special)?;
+                sidedefs.push(format!("sidedef {{ sector = {}; texturemiddle = \"{}\"; }}", zone, texture));
+                sides += 1;
+            },
+            _ => eprintln!("warning: level {}: wall {} has no zone on either side, so it's left out", clean_name(&level.name), i)
+        }
+    }
+
+    for s in sidedefs.iter() {
+        writeln!(w, "{}", s)?;
+    }
+
+    for (i, z) in level.zones.iter().enumerate() {
+        if tagged.contains(&(i as u16)) {
+            write!(w, "sector {{ id = {}; ", i + 1)?;
+        } else {
+            write!(w, "sector {{ ")?;
+        }
+        writeln!(w, "heightfloor = {}; heightceiling = {}; texturefloor = \"{}\"; textureceiling = \"{}\"; lightlevel = {}; user_flags = {}; }}",
+                 z.floor, z.ceiling, name(z.floor_texture), name(z.ceiling_texture),
+                 (255 - z.light as i32 * LIGHT_STEP).max(0), z.flags)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use gob::{Bitmap, RowOrder, Texture};
+    use gob::level::{Level, Vertex, Wall, Zone, NO_ZONE};
+    use gob::link::LinkedTexture;
+    use super::{lump_names, to_flat, to_patch, write_pwad, write_textmap, Lump};
+
+    fn bitmap(width: u32, height: u32) -> Bitmap<'static> {
+        let data = (0..width * height).map(|i| i as u8).collect::<Vec<_>>();
+        Bitmap::new(String::new(), width, height, 0, RowOrder::TopDown, data)
+    }
+
+    #[test]
+    fn tall_columns_are_split_into_relative_posts() {
+        let patch = to_patch(&bitmap(1, 300));
+        assert_eq!(&patch[..12], &[1, 0, 44, 1, 0, 0, 0, 0, 12, 0, 0, 0]);
+
+        // Posts of 128, 128 and 44 rows, the later ones starting 128 below the one before
+        let mut at = 12;
+        for &(delta, length, first) in [(0, 128, 0u8), (128, 128, 128), (128, 44, 0)].iter() {
+            assert_eq!(&patch[at..at + 4], &[delta, length, 0, first]);
+            at += length as usize + 4;
+        }
+        assert_eq!(&patch[at..], &[0xff]);
+    }
+
+    #[test]
+    fn flats_are_resampled_to_64_square() {
+        let flat = to_flat(&bitmap(2, 2));
+        assert_eq!(flat.len(), 64 * 64);
+        assert_eq!((flat[0], flat[31], flat[32], flat[63]), (0, 0, 1, 1));
+        assert_eq!((flat[32 * 64], flat[64 * 64 - 1]), (2, 3));
+    }
+
+    #[test]
+    fn pwad_directory_follows_the_lumps() {
+        let mut out = Vec::new();
+        write_pwad(&mut out, &[Lump::new("TEXTMAP", vec![1, 2, 3]), Lump::new("ENDMAP", Vec::new())]).unwrap();
+
+        assert_eq!(&out[..12], b"PWAD\x02\0\0\0\x0f\0\0\0");
+        assert_eq!(&out[12..15], &[1, 2, 3]);
+        assert_eq!(&out[15..31], b"\x0c\0\0\0\x03\0\0\0TEXTMAP\0");
+        assert_eq!(&out[31..], b"\x0f\0\0\0\0\0\0\0ENDMAP\0\0");
+    }
+
+    #[test]
+    fn made_up_lump_names_dont_clash_with_real_ones() {
+        let textures = |names: &[&str]| names.iter().map(|&n| LinkedTexture {
+            texture: Texture { name: String::from(n), bitmap_idx: 0, colour_idx: 0, pixel_size: 1.0 }, bitmap: None, palette: None
+        }).collect::<Vec<_>>();
+
+        assert_eq!(lump_names(&textures(&["", "ACT00000", "wall-1"])), ["ACT00000", "ACT00001", "WALL_1"]);
+        assert_eq!(lump_names(&textures(&["ACT00001", ""])), ["ACT00001", "ACT00003"]);
+    }
+
+    #[test]
+    fn walls_face_a_zone_and_the_player_starts_in_one() {
+        // A square zone whose third wall has it behind, plus a wall with no zone either
+        // side. The rebuild zone doesn't exist, so the start falls back to the square
+        let wall = |v1, v2, front, back| Wall { v1, v2, front, back, texture: 0, flags: 0 };
+        let level = Level {
+            name: String::from("E1L1"), rebuild_zone: 1,
+            vertices: vec![Vertex { x: 0, y: 0 }, Vertex { x: 0, y: 64 }, Vertex { x: 64, y: 64 }, Vertex { x: 64, y: 0 }],
+            walls: vec![wall(0, 1, 0, NO_ZONE), wall(1, 2, 0, NO_ZONE), wall(3, 2, NO_ZONE, 0), wall(3, 0, 0, NO_ZONE),
+                        wall(0, 2, 5, NO_ZONE)],
+            zones: vec![Zone { floor: 0, ceiling: 128, floor_texture: 0, ceiling_texture: 0, light: 0, flags: 0 }],
+            objects: Vec::new(), header_extra: Vec::new(), extra: Vec::new()
+        };
+
+        let mut out = Vec::new();
+        write_textmap(&level, &[String::from("WALL")], &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("thing { x = 32.000; y = 32.000; angle = 0; type = 1;"), "{}", text);
+        assert!(text.contains("linedef { v1 = 2; v2 = 3; sidefront = 2; blocking = true; }"), "{}", text);
+        assert_eq!(text.matches("linedef").count(), 4);
+        assert_eq!(text.matches("sidedef { sector = 0; texturemiddle = \"WALL\"; }").count(), 4);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use toml;
+
+use gob::Level;
+
+/// What an object kind is, for counting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Enemy,
+    Pickup,
+    Key,
+    Switch,
+    Other,
+    /// A kind the type table doesn't list.
+    Unknown
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Category::Enemy => "enemy",
+            Category::Pickup => "pickup",
+            Category::Key => "key",
+            Category::Switch => "switch",
+            Category::Other => "other",
+            Category::Unknown => "unknown"
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ObjectType {
+    pub kind: u16,
+    pub name: String,
+    pub category: Category
+}
+
+/// The built-in table, in the format `ObjectTypes::read` takes.
+const BUILT_IN: &str = include_str!("object_types.toml");
+
+/// Names and categories for object kinds, and the flag bits marking which difficulties an
+/// object appears on. Which kind number is which isn't recorded anywhere in the GOB, so
+/// the table ships with the tool in `object_types.toml` and can be added to from a TOML
+/// file:
+///
+/// ```text
+/// [difficulty]
+/// easy = 0x0100
+/// hard = 0x0400
+///
+/// [[object]]
+/// kind = 7
+/// name = "trooper"
+/// category = "enemy"
+/// ```
+///
+/// With no difficulties listed every object counts towards a single `all`.
+#[derive(Default, Deserialize)]
+pub struct ObjectTypes {
+    #[serde(default)]
+    pub object: Vec<ObjectType>,
+    #[serde(default)]
+    pub difficulty: BTreeMap<String, u16>
+}
+
+impl ObjectTypes {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<ObjectTypes, io::Error> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+
+        ObjectTypes::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<ObjectTypes, io::Error> {
+        toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The table built into the tool.
+    pub fn built_in() -> ObjectTypes {
+        ObjectTypes::parse(BUILT_IN).expect("the built-in object table is valid")
+    }
+
+    /// Lays `other` over this table: its entries replace ours for the same kind, and its
+    /// difficulties, if it lists any, replace ours, as the bits only make sense together.
+    pub fn overlay(&mut self, other: ObjectTypes) {
+        for t in other.object {
+            self.object.retain(|o| o.kind != t.kind);
+            self.object.push(t);
+        }
+        if !other.difficulty.is_empty() {
+            self.difficulty = other.difficulty;
+        }
+    }
+
+    pub fn lookup(&self, kind: u16) -> Option<&ObjectType> {
+        self.object.iter().find(|t| t.kind == kind)
+    }
+
+    /// Difficulties an object with these flags appears on.
+    pub fn difficulties(&self, flags: u16) -> Vec<String> {
+        if self.difficulty.is_empty() {
+            return vec![String::from("all")];
+        }
+
+        self.difficulty.iter().filter(|&(_, &mask)| flags & mask != 0).map(|(d, _)| d.clone()).collect()
+    }
+}
+
+/// An object as placed in a level, with its kind looked up. `angle` is in degrees,
+/// counter-clockwise from the +x axis.
+#[derive(Serialize)]
+pub struct Placement {
+    pub index: usize,
+    pub kind: u16,
+    pub name: String,
+    pub category: Category,
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub angle: f32,
+    pub flags: u16,
+    pub difficulties: Vec<String>
+}
+
+/// Every object in a level, in level order.
+pub fn catalogue(level: &Level, types: &ObjectTypes) -> Vec<Placement> {
+    level.objects.iter().enumerate().map(|(i, o)| {
+        let (name, category) = match types.lookup(o.kind) {
+            Some(t) => (t.name.clone(), t.category),
+            None => (format!("kind_{}", o.kind), Category::Unknown)
+        };
+
+        Placement {
+            index: i, kind: o.kind, name, category,
+            x: o.x, y: o.y, z: o.z, angle: o.angle as f32 * 360.0 / 65536.0, flags: o.flags,
+            difficulties: types.difficulties(o.flags)
+        }
+    }).collect()
+}
+
+/// How many objects of each category and each named kind appear on each difficulty, keyed
+/// by difficulty, then category or name.
+#[derive(Default, Serialize)]
+pub struct Counts {
+    pub categories: BTreeMap<String, BTreeMap<Category, usize>>,
+    pub kinds: BTreeMap<String, BTreeMap<String, usize>>
+}
+
+impl Counts {
+    pub fn new(placements: &[Placement]) -> Counts {
+        let mut counts = Counts::default();
+        for p in placements.iter() {
+            for d in p.difficulties.iter() {
+                *counts.categories.entry(d.clone()).or_insert_with(BTreeMap::new).entry(p.category).or_insert(0) += 1;
+                *counts.kinds.entry(d.clone()).or_insert_with(BTreeMap::new).entry(p.name.clone()).or_insert(0) += 1;
+            }
+        }
+
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Category, ObjectTypes};
+
+    #[test]
+    fn built_in_table_parses() {
+        ObjectTypes::built_in();
+    }
+
+    #[test]
+    fn overlay_replaces_kinds_and_difficulties() {
+        let mut types = ObjectTypes::parse("[difficulty]\neasy = 1\nhard = 2\n\n\
+                                            [[object]]\nkind = 7\nname = \"a\"\ncategory = \"enemy\"\n\n\
+                                            [[object]]\nkind = 8\nname = \"b\"\ncategory = \"key\"\n").unwrap();
+        types.overlay(ObjectTypes::parse("[[object]]\nkind = 7\nname = \"c\"\ncategory = \"pickup\"\n").unwrap());
+        assert_eq!(types.lookup(7).map(|t| (&t.name[..], t.category)), Some(("c", Category::Pickup)));
+        assert_eq!(types.lookup(8).map(|t| &t.name[..]), Some("b"));
+        assert_eq!(types.difficulties(3), ["easy", "hard"]);
+
+        types.overlay(ObjectTypes::parse("[difficulty]\nall = 0xffff\n").unwrap());
+        assert_eq!(types.difficulties(3), ["all"]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use gob::{clean_name, Bitmap, Palette, Texture};
+use gob::level::{Level, Vertex, Wall, Zone, Object};
+use gob::map::Gob;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Changed
+}
+
+/// One field that differs. `old` is `None` for something added and `new` for something
+/// removed.
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>
+}
+
+/// An item present in either GOB that isn't identical in both.
+#[derive(Debug, Serialize)]
+pub struct ItemDiff {
+    pub name: String,
+    pub status: Status,
+    pub old_index: Option<usize>,
+    pub new_index: Option<usize>,
+    pub changes: Vec<FieldChange>,
+    /// For bitmaps of the same size, how many pixels differ.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixels_changed: Option<usize>,
+    /// Where an image of the old, new and changed pixels was written, if one was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>
+}
+
+#[derive(Debug, Serialize)]
+pub struct GobDiff {
+    pub palettes: Vec<ItemDiff>,
+    pub bitmaps: Vec<ItemDiff>,
+    pub textures: Vec<ItemDiff>,
+    pub levels: Vec<ItemDiff>
+}
+
+impl GobDiff {
+    pub fn is_empty(&self) -> bool {
+        self.palettes.is_empty() && self.bitmaps.is_empty() && self.textures.is_empty() && self.levels.is_empty()
+    }
+}
+
+impl fmt::Display for GobDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(kind, items) in [("palette", &self.palettes), ("bitmap", &self.bitmaps),
+                               ("texture", &self.textures), ("level", &self.levels)].iter() {
+            for item in items.iter() {
+                let index = match (item.old_index, item.new_index) {
+                    (Some(o), Some(n)) if o == n => format!("{}", o),
+                    (Some(o), Some(n)) => format!("{} -> {}", o, n),
+                    (Some(o), None) => format!("{}", o),
+                    (None, Some(n)) => format!("{}", n),
+                    (None, None) => String::new()
+                };
+                writeln!(f, "{:?} {} {} ({})", item.status, kind, item.name, index)?;
+
+                for c in item.changes.iter() {
+                    match (&c.old, &c.new) {
+                        (Some(o), Some(n)) => writeln!(f, "    {}: {} -> {}", c.field, o, n)?,
+                        (&None, Some(n)) => writeln!(f, "    {}: added {}", c.field, n)?,
+                        (Some(o), &None) => writeln!(f, "    {}: removed {}", c.field, o)?,
+                        (&None, &None) => writeln!(f, "    {}", c.field)?
+                    }
+                }
+                if let Some(n) = item.pixels_changed {
+                    writeln!(f, "    {} pixels changed", n)?;
+                }
+                if let Some(ref path) = item.image {
+                    writeln!(f, "    see {}", path)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compares two GOBs. Bitmaps, textures and levels are matched by name, and by order
+/// among items sharing a name, so moving one doesn't show up as a change; palettes have
+/// no names, so they're matched by index.
+pub fn diff(old: &Gob, new: &Gob) -> Result<GobDiff, io::Error> {
+    let mut palettes = Vec::new();
+    for i in 0..old.index.palette_count().max(new.index.palette_count()) {
+        let a = if i < old.index.palette_count() { Some(old.palette(i)?) } else { None };
+        let b = if i < new.index.palette_count() { Some(new.palette(i)?) } else { None };
+        if let Some(d) = compare(format!("palette_{}", i), Some(i).filter(|_| a.is_some()), Some(i).filter(|_| b.is_some()),
+                                 a.as_ref().map(palette_fields), b.as_ref().map(palette_fields)) {
+            palettes.push(d);
+        }
+    }
+
+    let mut bitmaps = Vec::new();
+    for (name, a, b) in matched(names(old.index.bitmap_count(), |i| old.bitmap_header(i).map(|h| h.name))?,
+                                names(new.index.bitmap_count(), |i| new.bitmap_header(i).map(|h| h.name))?) {
+        let (ba, bb) = (a.map(|i| old.bitmap(i)).transpose()?, b.map(|i| new.bitmap(i)).transpose()?);
+        let mut d = compare(name.clone(), a, b, ba.as_ref().map(bitmap_fields), bb.as_ref().map(bitmap_fields));
+
+        if let (Some(ba), Some(bb)) = (ba.as_ref(), bb.as_ref()) {
+            if ba.width == bb.width && ba.height == bb.height && ba.bottom_up() != bb.bottom_up() {
+                let changed = ba.bottom_up().iter().zip(bb.bottom_up().iter()).filter(|&(p, q)| p != q).count();
+                let mut item = d.unwrap_or_else(|| ItemDiff { name: name.clone(), status: Status::Changed, old_index: a,
+                                                              new_index: b, changes: Vec::new(), pixels_changed: None,
+                                                              image: None });
+                item.pixels_changed = Some(changed);
+                d = Some(item);
+            }
+        }
+
+        bitmaps.extend(d);
+    }
+
+    let mut textures = Vec::new();
+    for (name, a, b) in matched(names(old.index.texture_count(), |i| old.texture(i).map(|t| t.name))?,
+                                names(new.index.texture_count(), |i| new.texture(i).map(|t| t.name))?) {
+        let fields = |t: Texture| vec![
+            (String::from("bitmap_idx"), t.bitmap_idx.to_string()),
+            (String::from("colour_idx"), t.colour_idx.to_string()),
+            (String::from("pixel_size"), t.pixel_size.to_string())
+        ];
+        let fa = a.map(|i| old.texture(i)).transpose()?.map(&fields);
+        let fb = b.map(|i| new.texture(i)).transpose()?.map(&fields);
+        textures.extend(compare(name, a, b, fa, fb));
+    }
+
+    let mut levels = Vec::new();
+    for (name, a, b) in matched(names(old.index.level_count(), |i| old.level_name(i))?,
+                                names(new.index.level_count(), |i| new.level_name(i))?) {
+        let (la, lb) = (a.map(|i| old.level(i)).transpose()?, b.map(|i| new.level(i)).transpose()?);
+        levels.extend(match (la, lb) {
+            (Some(la), Some(lb)) => {
+                let changes = level_changes(&la, &lb);
+                if changes.is_empty() {
+                    None
+                } else {
+                    Some(ItemDiff { name, status: Status::Changed, old_index: a, new_index: b, changes,
+                                    pixels_changed: None, image: None })
+                }
+            },
+            (la, lb) => compare(name, a, b, la.map(|_| Vec::new()), lb.map(|_| Vec::new()))
+        });
+    }
+
+    Ok(GobDiff { palettes, bitmaps, textures, levels })
+}
+
+/// Clean names of `count` items.
+fn names<F>(count: usize, name: F) -> Result<Vec<String>, io::Error>
+    where F: Fn(usize) -> Result<String, io::Error>
+{
+    (0..count).map(|i| name(i).map(|n| String::from(clean_name(&n)))).collect()
+}
+
+/// Pairs up items by name, the nth item called something in one GOB going with the nth
+/// item of that name in the other. Gives each name with its old and new index, in the old
+/// GOB's order followed by anything only the new one has.
+fn matched(old: Vec<String>, new: Vec<String>) -> Vec<(String, Option<usize>, Option<usize>)> {
+    let mut new_by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, n) in new.iter().enumerate().rev() {
+        new_by_name.entry(&n[..]).or_default().push(i);
+    }
+
+    let mut pairs = Vec::new();
+    let mut taken = vec![false; new.len()];
+    for (i, n) in old.iter().enumerate() {
+        let j = new_by_name.get_mut(&n[..]).and_then(|v| v.pop());
+        if let Some(j) = j {
+            taken[j] = true;
+        }
+        pairs.push((n.clone(), Some(i), j));
+    }
+    for (j, n) in new.iter().enumerate().filter(|&(j, _)| !taken[j]) {
+        pairs.push((n.clone(), None, Some(j)));
+    }
+
+    pairs
+}
+
+/// Compares two items' fields, giving `None` if they're the same.
+fn compare(name: String, a: Option<usize>, b: Option<usize>,
+           fa: Option<Vec<(String, String)>>, fb: Option<Vec<(String, String)>>) -> Option<ItemDiff> {
+    let (status, changes) = match (fa, fb) {
+        (Some(fa), Some(fb)) => {
+            let changes = field_changes(&fa, &fb);
+            if changes.is_empty() {
+                return None;
+            }
+            (Status::Changed, changes)
+        },
+        (Some(_), None) => (Status::Removed, Vec::new()),
+        (None, Some(_)) => (Status::Added, Vec::new()),
+        (None, None) => return None
+    };
+
+    Some(ItemDiff { name, status, old_index: a, new_index: b, changes, pixels_changed: None, image: None })
+}
+
+fn field_changes(a: &[(String, String)], b: &[(String, String)]) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    for i in 0..a.len().max(b.len()) {
+        let (fa, fb) = (a.get(i), b.get(i));
+        if fa.map(|f| &f.1) != fb.map(|f| &f.1) {
+            changes.push(FieldChange {
+                field: fa.or(fb).unwrap().0.clone(),
+                old: fa.map(|f| f.1.clone()),
+                new: fb.map(|f| f.1.clone())
+            });
+        }
+    }
+
+    changes
+}
+
+fn palette_fields(p: &Palette) -> Vec<(String, String)> {
+    let mut fields = vec![(String::from("depth"), format!("{:?}", p.depth))];
+    fields.extend((0..256).map(|i| (format!("entry {}", i), format!("#{:02x}{:02x}{:02x}", p.r[i], p.g[i], p.b[i]))));
+    fields
+}
+
+fn bitmap_fields(b: &Bitmap) -> Vec<(String, String)> {
+    vec![(String::from("width"), b.width.to_string()),
+         (String::from("height"), b.height.to_string()),
+         (String::from("flags"), format!("{:#06x}", b.flags))]
+}
+
+fn vertex_fields(v: &Vertex) -> Vec<(String, String)> {
+    vec![(String::from("position"), format!("({}, {})", v.x, v.y))]
+}
+
+fn wall_fields(w: &Wall) -> Vec<(String, String)> {
+    vec![(String::from("v1"), w.v1.to_string()), (String::from("v2"), w.v2.to_string()),
+         (String::from("front"), w.front.to_string()), (String::from("back"), w.back.to_string()),
+         (String::from("texture"), w.texture.to_string()), (String::from("flags"), format!("{:#06x}", w.flags))]
+}
+
+fn zone_fields(z: &Zone) -> Vec<(String, String)> {
+    vec![(String::from("floor"), z.floor.to_string()), (String::from("ceiling"), z.ceiling.to_string()),
+         (String::from("floor_texture"), z.floor_texture.to_string()),
+         (String::from("ceiling_texture"), z.ceiling_texture.to_string()),
+         (String::from("light"), z.light.to_string()), (String::from("flags"), format!("{:#06x}", z.flags))]
+}
+
+fn object_fields(o: &Object) -> Vec<(String, String)> {
+    vec![(String::from("kind"), o.kind.to_string()), (String::from("position"), format!("({}, {}, {})", o.x, o.y, o.z)),
+         (String::from("angle"), o.angle.to_string()), (String::from("flags"), format!("{:#06x}", o.flags))]
+}
+
+/// One line per field of each record, as `"<kind> <index>.<field>"`.
+fn record_fields<T, F>(kind: &str, records: &[T], fields: F) -> Vec<(String, String)>
+    where F: Fn(&T) -> Vec<(String, String)>
+{
+    records.iter().enumerate()
+        .flat_map(|(i, r)| fields(r).into_iter().map(move |(f, v)| (format!("{} {}.{}", kind, i, f), v)))
+        .collect()
+}
+
+/// Field level differences between two levels' headers and every vertex, wall, zone and
+/// object, matched by index.
+fn level_changes(a: &Level, b: &Level) -> Vec<FieldChange> {
+    let mut changes = field_changes(&level_fields(a), &level_fields(b));
+    changes.extend(records_changes("vertex", &a.vertices, &b.vertices, vertex_fields));
+    changes.extend(records_changes("wall", &a.walls, &b.walls, wall_fields));
+    changes.extend(records_changes("zone", &a.zones, &b.zones, zone_fields));
+    changes.extend(records_changes("object", &a.objects, &b.objects, object_fields));
+
+    changes
+}
+
+fn level_fields(l: &Level) -> Vec<(String, String)> {
+    vec![(String::from("rebuild_zone"), l.rebuild_zone.to_string()),
+         (String::from("vertices"), l.vertices.len().to_string()),
+         (String::from("walls"), l.walls.len().to_string()),
+         (String::from("zones"), l.zones.len().to_string()),
+         (String::from("objects"), l.objects.len().to_string()),
+         (String::from("header_extra"), hex(&l.header_extra)),
+         (String::from("extra"), hex(&l.extra))]
+}
+
+/// Changes to records present in both levels, field by field; records only one side has
+/// are reported whole.
+fn records_changes<T, F>(kind: &str, a: &[T], b: &[T], fields: F) -> Vec<FieldChange>
+    where F: Fn(&T) -> Vec<(String, String)>
+{
+    let common = a.len().min(b.len());
+    let mut changes = field_changes(&record_fields(kind, &a[..common], &fields), &record_fields(kind, &b[..common], &fields));
+
+    let summary = |r: &T| fields(r).iter().map(|(f, v)| format!("{} {}", f, v)).collect::<Vec<_>>().join(", ");
+    for (i, r) in a.iter().enumerate().skip(common) {
+        changes.push(FieldChange { field: format!("{} {}", kind, i), old: Some(summary(r)), new: None });
+    }
+    for (i, r) in b.iter().enumerate().skip(common) {
+        changes.push(FieldChange { field: format!("{} {}", kind, i), old: None, new: Some(summary(r)) });
+    }
+
+    changes
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matched;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&n| String::from(n)).collect()
+    }
+
+    #[test]
+    fn matches_by_name_not_position() {
+        let pairs = matched(names(&["E1L1", "E1L2", "E1L3"]), names(&["E1L2", "E1L1", "E1L4"]));
+        assert_eq!(pairs, [(String::from("E1L1"), Some(0), Some(1)), (String::from("E1L2"), Some(1), Some(0)),
+                           (String::from("E1L3"), Some(2), None), (String::from("E1L4"), None, Some(2))]);
+    }
+
+    #[test]
+    fn shared_names_pair_in_order() {
+        let pairs = matched(names(&["wall", "wall"]), names(&["door", "wall", "wall"]));
+        assert_eq!(pairs, [(String::from("wall"), Some(0), Some(1)), (String::from("wall"), Some(1), Some(2)),
+                           (String::from("door"), None, Some(0))]);
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use gob::{clean_name, write_level_data, TAG_LEVEL};
+use gob::level::{Level, Object, Vertex, Zone, NO_ZONE};
+use gob::link::LinkedTexture;
+use gob::trigger::{not_a_trigger_list, TriggerSource};
+use gob::map::Gob;
+use gob::write::rewrite;
+
+/// Most records of one kind a level can hold, as the counts are stored in 16 bits.
+const MAX_RECORDS: usize = 0xffff;
+
+/// The kind of thing a level reference points at. Textures are the GOB's; everything
+/// else is the level's own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefTarget {
+    Vertex,
+    Wall,
+    Zone,
+    Object,
+    Texture
+}
+
+impl fmt::Display for RefTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RefTarget::Vertex => "vertex",
+            RefTarget::Wall => "wall",
+            RefTarget::Zone => "zone",
+            RefTarget::Object => "object",
+            RefTarget::Texture => "texture"
+        })
+    }
+}
+
+/// A reference in a level that points past the end of what it refers to.
+pub struct DanglingLevelRef {
+    pub item: &'static str,
+    pub index: usize,
+    pub field: &'static str,
+    pub target: RefTarget,
+    pub value: usize,
+    pub count: usize
+}
+
+impl fmt::Display for DanglingLevelRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let owner = if self.target == RefTarget::Texture { "GOB" } else { "level" };
+        write!(f, "{} {} {} refers to {} {}, but the {} only has {}",
+               self.item, self.index, self.field, self.target, self.value, owner, self.count)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn check_index(kind: &str, idx: usize, count: usize) -> Result<(), io::Error> {
+    if idx >= count {
+        return Err(invalid(format!("no {} {}, the level has {}", kind, idx, count)));
+    }
+
+    Ok(())
+}
+
+fn check_room(kind: &str, count: usize) -> Result<(), io::Error> {
+    if count >= MAX_RECORDS {
+        return Err(invalid(format!("a level can't hold more than {} {}", MAX_RECORDS, kind)));
+    }
+
+    Ok(())
+}
+
+/// Index of the first texture with this name, ignoring case and padding.
+pub fn find_texture(textures: &[LinkedTexture], name: &str) -> Option<u16> {
+    textures.iter()
+        .position(|t| clean_name(&t.texture.name).eq_ignore_ascii_case(clean_name(name)))
+        .map(|i| i as u16)
+}
+
+impl Level {
+    /// Moves a vertex, failing without changing anything if there's no such vertex. Like
+    /// every edit here, it leaves the level's references intact.
+    pub fn move_vertex(&mut self, idx: u16, x: i16, y: i16) -> Result<(), io::Error> {
+        check_index("vertex", idx as usize, self.vertices.len())?;
+        self.vertices[idx as usize] = Vertex { x, y };
+
+        Ok(())
+    }
+
+    pub fn add_vertex(&mut self, x: i16, y: i16) -> Result<u16, io::Error> {
+        check_room("vertices", self.vertices.len())?;
+        self.vertices.push(Vertex { x, y });
+
+        Ok((self.vertices.len() - 1) as u16)
+    }
+
+    /// Removes a vertex no wall uses, shifting walls' references to the vertices after it
+    /// down to match.
+    pub fn remove_vertex(&mut self, idx: u16) -> Result<Vertex, io::Error> {
+        check_index("vertex", idx as usize, self.vertices.len())?;
+        let users: Vec<String> = self.walls.iter().enumerate()
+            .filter(|&(_, w)| w.v1 == idx || w.v2 == idx)
+            .map(|(i, _)| i.to_string())
+            .collect();
+        if !users.is_empty() {
+            return Err(invalid(format!("vertex {} is still used by walls {}", idx, users.join(", "))));
+        }
+
+        for w in self.walls.iter_mut() {
+            if w.v1 > idx {
+                w.v1 -= 1;
+            }
+            if w.v2 > idx {
+                w.v2 -= 1;
+            }
+        }
+
+        Ok(self.vertices.remove(idx as usize))
+    }
+
+    /// Points a wall at the texture called `name` in `textures`, the GOB's texture list.
+    pub fn retexture_wall(&mut self, wall: usize, name: &str, textures: &[LinkedTexture]) -> Result<(), io::Error> {
+        check_index("wall", wall, self.walls.len())?;
+        let texture = find_texture(textures, name).ok_or_else(|| invalid(format!("no texture called {}", name)))?;
+        self.walls[wall].texture = texture;
+
+        Ok(())
+    }
+
+    pub fn add_object(&mut self, object: Object) -> Result<usize, io::Error> {
+        check_room("objects", self.objects.len())?;
+        self.objects.push(object);
+
+        Ok(self.objects.len() - 1)
+    }
+
+    /// Removes an object no trigger uses. Triggers can only be checked and renumbered if
+    /// the level's extra bytes are a trigger list; if not, this warns and goes ahead.
+    pub fn remove_object(&mut self, idx: usize) -> Result<Object, io::Error> {
+        check_index("object", idx, self.objects.len())?;
+        match self.triggers() {
+            Some(mut triggers) => {
+                if triggers.iter().any(|t| t.source == TriggerSource::Object(idx as u16)) {
+                    return Err(invalid(format!("object {} is still used by a trigger", idx)));
+                }
+                for t in triggers.iter_mut() {
+                    if let TriggerSource::Object(ref mut o) = t.source {
+                        if *o as usize > idx {
+                            *o -= 1;
+                        }
+                    }
+                }
+                self.set_triggers(&triggers)?;
+            },
+            None => eprintln!("warning: level {}: {}, so triggers using object {} or later can't be checked or renumbered",
+                              clean_name(&self.name), not_a_trigger_list(self), idx)
+        }
+
+        Ok(self.objects.remove(idx))
+    }
+
+    /// A zone to change the heights, textures, light or flags of.
+    pub fn zone_mut(&mut self, idx: u16) -> Result<&mut Zone, io::Error> {
+        check_index("zone", idx as usize, self.zones.len())?;
+
+        Ok(&mut self.zones[idx as usize])
+    }
+
+    pub fn set_rebuild_zone(&mut self, zone: u16) -> Result<(), io::Error> {
+        check_index("zone", zone as usize, self.zones.len())?;
+        self.rebuild_zone = zone;
+
+        Ok(())
+    }
+
+    /// Every reference to a vertex, wall, zone, object or texture that doesn't exist. A
+    /// wall's back may be `NO_ZONE`; its front may not. Triggers are only checked if the
+    /// level's extra bytes are a trigger list.
+    pub fn dangling(&self, texture_count: usize) -> Vec<DanglingLevelRef> {
+        let mut out = Vec::new();
+        let (vertices, walls, zones, objects) = (self.vertices.len(), self.walls.len(), self.zones.len(), self.objects.len());
+        {
+            let mut check = |item, index, field, target, value: u16, count| if value as usize >= count {
+                out.push(DanglingLevelRef { item, index, field, target,
+                                            value: value as usize, count });
+            };
+
+            check("level", 0, "rebuild_zone", RefTarget::Zone, self.rebuild_zone, zones);
+            for (i, w) in self.walls.iter().enumerate() {
+                check("wall", i, "v1", RefTarget::Vertex, w.v1, vertices);
+                check("wall", i, "v2", RefTarget::Vertex, w.v2, vertices);
+                check("wall", i, "front", RefTarget::Zone, w.front, zones);
+                if w.back != NO_ZONE {
+                    check("wall", i, "back", RefTarget::Zone, w.back, zones);
+                }
+                check("wall", i, "texture", RefTarget::Texture, w.texture, texture_count);
+            }
+            for (i, z) in self.zones.iter().enumerate() {
+                check("zone", i, "floor_texture", RefTarget::Texture, z.floor_texture, texture_count);
+                check("zone", i, "ceiling_texture", RefTarget::Texture, z.ceiling_texture, texture_count);
+            }
+            for (i, t) in self.triggers().unwrap_or_default().iter().enumerate() {
+                match t.source {
+                    TriggerSource::Wall(w) => check("trigger", i, "source", RefTarget::Wall, w, walls),
+                    TriggerSource::Zone(z) => check("trigger", i, "source", RefTarget::Zone, z, zones),
+                    TriggerSource::Object(o) => check("trigger", i, "source", RefTarget::Object, o, objects)
+                }
+                check("trigger", i, "target_zone", RefTarget::Zone, t.target_zone, zones);
+            }
+        }
+
+        out
+    }
+}
+
+/// Writes `gob` back out with the levels in `levels` (keyed by level index) swapped in.
+/// Fails before writing anything if an index is out of range or a level has a dangling
+/// reference.
+pub fn rewrite_levels<W: Write>(gob: &Gob, buf: &mut W, levels: &HashMap<usize, Level>) -> Result<(), io::Error> {
+    let positions: Vec<usize> = gob.index.tags.iter().enumerate()
+        .filter(|&(_, t)| t.id == TAG_LEVEL)
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut replace = HashMap::new();
+    for (&idx, level) in levels.iter() {
+        let &position = positions.get(idx)
+            .ok_or_else(|| invalid(format!("no level {}, the GOB has {}", idx, positions.len())))?;
+        if let Some(d) = level.dangling(gob.index.texture_count()).first() {
+            return Err(invalid(format!("level {} ({}): {}", idx, clean_name(&level.name), d)));
+        }
+        if level.triggers().is_none() {
+            eprintln!("warning: level {} ({}): {}, so its triggers weren't checked", idx, clean_name(&level.name), not_a_trigger_list(level));
+        }
+
+        let mut payload = Vec::new();
+        write_level_data(level, &mut payload)?;
+        replace.insert(position, payload);
+    }
+
+    rewrite(gob, buf, &replace)
+}
+
+/// A zone field `--zone` can set, holding its new value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZoneField {
+    Floor(i16),
+    Ceiling(i16),
+    FloorTexture(u16),
+    CeilingTexture(u16),
+    Light(u16),
+    Flags(u16)
+}
+
+impl ZoneField {
+    /// Parses `field=value`, with the value in the field's own range.
+    fn parse(arg: &str) -> Result<ZoneField, String> {
+        let mut split = arg.splitn(2, '=');
+        let (field, value) = (split.next().unwrap_or(""), split.next().ok_or_else(|| format!("{} has no value", arg))?);
+        match field {
+            "floor" => signed(value).map(ZoneField::Floor),
+            "ceiling" => signed(value).map(ZoneField::Ceiling),
+            "floor_texture" => unsigned(value).map(ZoneField::FloorTexture),
+            "ceiling_texture" => unsigned(value).map(ZoneField::CeilingTexture),
+            "light" => unsigned(value).map(ZoneField::Light),
+            "flags" => unsigned(value).map(ZoneField::Flags),
+            _ => Err(format!("zones have no field {}", field))
+        }
+    }
+
+    fn set(&self, zone: &mut Zone) {
+        match *self {
+            ZoneField::Floor(v) => zone.floor = v,
+            ZoneField::Ceiling(v) => zone.ceiling = v,
+            ZoneField::FloorTexture(v) => zone.floor_texture = v,
+            ZoneField::CeilingTexture(v) => zone.ceiling_texture = v,
+            ZoneField::Light(v) => zone.light = v,
+            ZoneField::Flags(v) => zone.flags = v
+        }
+    }
+}
+
+impl fmt::Display for ZoneField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ZoneField::Floor(v) => write!(f, "floor to {}", v),
+            ZoneField::Ceiling(v) => write!(f, "ceiling to {}", v),
+            ZoneField::FloorTexture(v) => write!(f, "floor texture to {}", v),
+            ZoneField::CeilingTexture(v) => write!(f, "ceiling texture to {}", v),
+            ZoneField::Light(v) => write!(f, "light to {}", v),
+            ZoneField::Flags(v) => write!(f, "flags to {}", v)
+        }
+    }
+}
+
+// Each value parses straight into its field's type, so one out of range is rejected
+// rather than wrapped
+fn unsigned(s: &str) -> Result<u16, String> {
+    s.parse().map_err(|_| format!("{} isn't a whole number from 0 to 65535", s))
+}
+
+fn signed(s: &str) -> Result<i16, String> {
+    s.parse().map_err(|_| format!("{} isn't a whole number from -32768 to 32767", s))
+}
+
+/// One edit given on the command line, applied in order so each sees the level as the
+/// ones before left it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelEdit {
+    MoveVertex(u16, i16, i16),
+    AddVertex(i16, i16),
+    RemoveVertex(u16),
+    RetextureWall(usize, String),
+    AddObject(Object),
+    RemoveObject(usize),
+    SetZone(u16, ZoneField),
+    RebuildZone(u16)
+}
+
+impl LevelEdit {
+    /// Parses `--move-vertex=i,x,y`, `--add-vertex=x,y`, `--remove-vertex=i`,
+    /// `--retexture-wall=i,NAME`, `--add-object=kind,x,y[,z,angle,flags]`,
+    /// `--remove-object=i`, `--zone=i,field=value` or `--rebuild-zone=i`. `None` means
+    /// `arg` isn't an edit at all; an edit with bad values is an error saying what's wrong.
+    pub fn parse(arg: &str) -> Option<Result<LevelEdit, String>> {
+        let mut split = arg.splitn(2, '=');
+        let (name, value) = (split.next()?, split.next().unwrap_or(""));
+        let usage = match name {
+            "--move-vertex" => "<vertex>,<x>,<y>",
+            "--add-vertex" => "<x>,<y>",
+            "--remove-vertex" => "<vertex>",
+            "--retexture-wall" => "<wall>,<texture name>",
+            "--add-object" => "<kind>,<x>,<y>[,<z>,<angle>,<flags>]",
+            "--remove-object" => "<object>",
+            "--zone" => "<zone>,<field>=<value>",
+            "--rebuild-zone" => "<zone>",
+            _ => return None
+        };
+
+        let parts: Vec<&str> = value.split(',').map(|p| p.trim()).collect();
+        Some(LevelEdit::parse_values(name, usage, &parts).map_err(|e| format!("{}: {}", arg, e)))
+    }
+
+    /// The edit `name` with these values. `usage` says what it takes, for when the count
+    /// is wrong.
+    fn parse_values(name: &str, usage: &str, parts: &[&str]) -> Result<LevelEdit, String> {
+        let u = |i: usize| unsigned(parts[i]);
+        let s = |i: usize| signed(parts[i]);
+        let index = |i: usize| parts[i].parse::<usize>().map_err(|_| format!("{} isn't an index", parts[i]));
+
+        Ok(match (name, parts.len()) {
+            ("--move-vertex", 3) => LevelEdit::MoveVertex(u(0)?, s(1)?, s(2)?),
+            ("--add-vertex", 2) => LevelEdit::AddVertex(s(0)?, s(1)?),
+            ("--remove-vertex", 1) => LevelEdit::RemoveVertex(u(0)?),
+            ("--retexture-wall", 2) => LevelEdit::RetextureWall(index(0)?, String::from(parts[1])),
+            ("--add-object", 3) => LevelEdit::AddObject(Object { kind: u(0)?, x: s(1)?, y: s(2)?, z: 0, angle: 0, flags: 0 }),
+            ("--add-object", 6) => LevelEdit::AddObject(Object { kind: u(0)?, x: s(1)?, y: s(2)?, z: s(3)?, angle: u(4)?, flags: u(5)? }),
+            ("--remove-object", 1) => LevelEdit::RemoveObject(index(0)?),
+            ("--zone", 2) => LevelEdit::SetZone(u(0)?, ZoneField::parse(parts[1])?),
+            ("--rebuild-zone", 1) => LevelEdit::RebuildZone(u(0)?),
+            _ => return Err(format!("expected {}={}", name, usage))
+        })
+    }
+
+    pub fn apply(&self, level: &mut Level, textures: &[LinkedTexture]) -> Result<(), io::Error> {
+        match *self {
+            LevelEdit::MoveVertex(v, x, y) => level.move_vertex(v, x, y),
+            LevelEdit::AddVertex(x, y) => level.add_vertex(x, y).map(|_| ()),
+            LevelEdit::RemoveVertex(v) => level.remove_vertex(v).map(|_| ()),
+            LevelEdit::RetextureWall(w, ref name) => level.retexture_wall(w, name, textures),
+            LevelEdit::AddObject(o) => level.add_object(o).map(|_| ()),
+            LevelEdit::RemoveObject(o) => level.remove_object(o).map(|_| ()),
+            LevelEdit::SetZone(z, field) => level.zone_mut(z).map(|zone| field.set(zone)),
+            LevelEdit::RebuildZone(z) => level.set_rebuild_zone(z)
+        }
+    }
+}
+
+impl fmt::Display for LevelEdit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LevelEdit::MoveVertex(v, x, y) => write!(f, "move vertex {} to ({}, {})", v, x, y),
+            LevelEdit::AddVertex(x, y) => write!(f, "add a vertex at ({}, {})", x, y),
+            LevelEdit::RemoveVertex(v) => write!(f, "remove vertex {}", v),
+            LevelEdit::RetextureWall(w, ref name) => write!(f, "give wall {} texture {}", w, name),
+            LevelEdit::AddObject(o) => write!(f, "add an object of kind {} at ({}, {}, {})", o.kind, o.x, o.y, o.z),
+            LevelEdit::RemoveObject(o) => write!(f, "remove object {}", o),
+            LevelEdit::SetZone(z, field) => write!(f, "set zone {}'s {}", z, field),
+            LevelEdit::RebuildZone(z) => write!(f, "make zone {} the rebuild zone", z)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LevelEdit, ZoneField};
+
+    #[test]
+    fn parses_edits() {
+        assert_eq!(LevelEdit::parse("--move-vertex=3,-64,128"), Some(Ok(LevelEdit::MoveVertex(3, -64, 128))));
+        assert!(matches!(LevelEdit::parse("--add-object=7,16,-16,0,16384,256"),
+                         Some(Ok(LevelEdit::AddObject(o))) if o.angle == 16384 && o.flags == 256));
+        assert_eq!(LevelEdit::parse("--zone=0,floor=-32"), Some(Ok(LevelEdit::SetZone(0, ZoneField::Floor(-32)))));
+        assert_eq!(LevelEdit::parse("--zone=2,light=5").unwrap().unwrap().to_string(), "set zone 2's light to 5");
+        assert_eq!(LevelEdit::parse("--strict"), None);
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        for arg in ["--move-vertex=65536,0,0", "--add-vertex=40000,0", "--remove-vertex=-1", "--add-object=7,0,0,0,70000,0",
+                    "--zone=0,ceiling=32768", "--zone=0,light=-1", "--remove-object=x"].iter() {
+            assert!(matches!(LevelEdit::parse(arg), Some(Err(_))), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_zone_fields_and_wrong_counts() {
+        assert_eq!(LevelEdit::parse("--zone=0,bogus=5"), Some(Err(String::from("--zone=0,bogus=5: zones have no field bogus"))));
+        assert_eq!(LevelEdit::parse("--move-vertex=1,2"), Some(Err(String::from("--move-vertex=1,2: expected --move-vertex=<vertex>,<x>,<y>"))));
+        assert!(matches!(LevelEdit::parse("--rebuild-zone"), Some(Err(_))));
+    }
+}
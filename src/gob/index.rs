@@ -0,0 +1,282 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use gob::{Palette, PaletteDepth, Bitmap, Texture, Level};
+use gob::{read_header, read_palette_data, read_bitmap_data, read_texture_data, read_level_data, read_file_info};
+use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_LEVEL, TAG_FILE_INFO, TAG_HEADER_SIZE};
+
+/// Location of a single tag in a GOB. `offset` points at the tag payload (just past the
+/// 6 byte header) and `size` is the payload length, i.e. the stored tag_size minus the header.
+#[derive(Clone, Copy, Debug)]
+pub struct TagEntry {
+    pub id: u16,
+    pub offset: u64,
+    pub size: u32
+}
+
+/// Table of contents for a GOB, built in a single pass over the tag headers without
+/// decoding any payloads. Palettes, bitmaps, textures and levels are numbered in file order,
+/// which is how textures refer to their bitmap and palette.
+///
+/// When `strict` is set, a tag running past the end of the file or a reader that doesn't
+/// consume exactly the tag's payload is an error rather than a warning. `palette_depth`
+/// overrides 6/8 bit palette detection.
+pub struct GobIndex {
+    pub tags: Vec<TagEntry>,
+    pub strict: bool,
+    pub palette_depth: Option<PaletteDepth>,
+    palettes: Vec<usize>,
+    bitmaps: Vec<usize>,
+    textures: Vec<usize>,
+    levels: Vec<usize>
+}
+
+impl GobIndex {
+    /// Indexes every whole tag. Without `strict`, a last tag cut short by the end of the
+    /// file is left out with a warning, and its bytes count as trailing data.
+    pub fn build<R: Read + Seek>(buf: &mut R, strict: bool) -> Result<GobIndex, io::Error> {
+        let mut index = GobIndex {
+            tags: Vec::new(),
+            strict,
+            palette_depth: None,
+            palettes: Vec::new(),
+            bitmaps: Vec::new(),
+            textures: Vec::new(),
+            levels: Vec::new()
+        };
+
+        let end = buf.seek(SeekFrom::End(0))?;
+        let mut pos = buf.seek(SeekFrom::Start(0))?;
+
+        while pos + TAG_HEADER_SIZE as u64 <= end {
+            let (id, tag_size) = read_header(buf)?;
+            if tag_size < TAG_HEADER_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("tag {} at offset {} has invalid size {}", id, pos, tag_size)));
+            }
+            if pos + tag_size as u64 > end {
+                let msg = format!("tag {} at offset {} has size {}, past the end of the file at {}", id, pos, tag_size, end);
+                if strict {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+
+                eprintln!("warning: {}", msg);
+                break;
+            }
+
+            let entry = TagEntry { id, offset: pos + TAG_HEADER_SIZE as u64, size: tag_size - TAG_HEADER_SIZE };
+            let slot = index.tags.len();
+            match id {
+                TAG_PALETTE => index.palettes.push(slot),
+                TAG_BITMAP => index.bitmaps.push(slot),
+                TAG_TEXTURE => index.textures.push(slot),
+                TAG_LEVEL => index.levels.push(slot),
+                _ => {}
+            }
+            index.tags.push(entry);
+
+            pos = buf.seek(SeekFrom::Start(pos + tag_size as u64))?;
+        }
+
+        Ok(index)
+    }
+
+    pub fn palette_count(&self) -> usize {
+        self.palettes.len()
+    }
+
+    pub fn bitmap_count(&self) -> usize {
+        self.bitmaps.len()
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn palette_entry(&self, idx: usize) -> Option<&TagEntry> {
+        self.palettes.get(idx).map(|&slot| &self.tags[slot])
+    }
+
+    pub fn bitmap_entry(&self, idx: usize) -> Option<&TagEntry> {
+        self.bitmaps.get(idx).map(|&slot| &self.tags[slot])
+    }
+
+    pub fn texture_entry(&self, idx: usize) -> Option<&TagEntry> {
+        self.textures.get(idx).map(|&slot| &self.tags[slot])
+    }
+
+    pub fn level_entry(&self, idx: usize) -> Option<&TagEntry> {
+        self.levels.get(idx).map(|&slot| &self.tags[slot])
+    }
+
+    pub fn read_palette<R: Read + Seek>(&self, buf: &mut R, idx: usize) -> Result<Palette, io::Error> {
+        let entry = lookup(self.palette_entry(idx), "palette", idx)?;
+        self.decode(buf, entry, |b| read_palette_data(b, self.palette_depth))
+    }
+
+    /// Decodes a bitmap into pixels of its own, with its rows bottom-up as stored. `Gob`
+    /// borrows its bitmaps from the mapping instead, so only other sources use this.
+    #[allow(dead_code)]
+    pub fn read_bitmap<R: Read + Seek>(&self, buf: &mut R, idx: usize) -> Result<Bitmap<'static>, io::Error> {
+        let entry = lookup(self.bitmap_entry(idx), "bitmap", idx)?;
+        self.decode(buf, entry, |b| read_bitmap_data(b))
+    }
+
+    pub fn read_texture<R: Read + Seek>(&self, buf: &mut R, idx: usize) -> Result<Texture, io::Error> {
+        let entry = lookup(self.texture_entry(idx), "texture", idx)?;
+        self.decode(buf, entry, |b| read_texture_data(b))
+    }
+
+    pub fn read_level<R: Read + Seek>(&self, buf: &mut R, idx: usize) -> Result<Level, io::Error> {
+        let entry = lookup(self.level_entry(idx), "level", idx)?;
+        self.decode(buf, entry, |b| read_level_data(b, entry.size))
+    }
+
+    /// Runs `read` over a reader bounded to exactly the tag's payload, then checks that it
+    /// consumed all of it.
+    pub fn decode<R, F, T>(&self, buf: &mut R, entry: &TagEntry, read: F) -> Result<T, io::Error>
+        where R: Read + Seek, F: FnOnce(&mut io::Take<&mut R>) -> Result<T, io::Error>
+    {
+        buf.seek(SeekFrom::Start(entry.offset))?;
+        let mut tag = buf.take(entry.size as u64);
+        let value = read(&mut tag).map_err(|e| overrun(entry, e))?;
+
+        check_consumed(entry, entry.size as u64 - tag.limit(), self.strict)?;
+        Ok(value)
+    }
+
+    /// Prints the GOB info block(s), if the file has any.
+    pub fn print_file_info<R: Read + Seek>(&self, buf: &mut R) -> Result<(), io::Error> {
+        for entry in self.tags.iter().filter(|t| t.id == TAG_FILE_INFO) {
+            self.decode(buf, entry, |b| read_file_info(b))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn lookup<'a>(entry: Option<&'a TagEntry>, kind: &str, idx: usize) -> Result<&'a TagEntry, io::Error> {
+    entry.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} with index {}", kind, idx)))
+}
+
+/// Compares the bytes a reader consumed against the tag's payload size. A short read is
+/// reported as a warning, or as an error in strict mode.
+pub fn check_consumed(entry: &TagEntry, consumed: u64, strict: bool) -> Result<(), io::Error> {
+    if consumed == entry.size as u64 {
+        return Ok(());
+    }
+
+    let msg = format!("tag {} at offset {}: reader consumed {} of {} bytes", entry.id, entry.offset, consumed, entry.size);
+    if strict {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+
+    eprintln!("warning: {}", msg);
+    Ok(())
+}
+
+/// Turns running off the end of a tag's payload into an error naming the tag.
+pub fn overrun(entry: &TagEntry, err: io::Error) -> io::Error {
+    if err.kind() != io::ErrorKind::UnexpectedEof {
+        return err;
+    }
+
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("tag {} at offset {}: reader needs more than the {} bytes in the tag", entry.id, entry.offset, entry.size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_LEVEL};
+    use gob::write::{write_header, write_tag};
+    use super::{GobIndex, TagEntry, check_consumed};
+
+    fn gob(tags: &[(u16, usize)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for &(id, size) in tags.iter() {
+            write_tag(&mut buf, id, &vec![0xaa; size]).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn numbers_tags_by_kind_in_file_order() {
+        let buf = gob(&[(TAG_PALETTE, 768), (TAG_BITMAP, 30), (20, 112), (TAG_BITMAP, 22), (TAG_TEXTURE, 40), (TAG_LEVEL, 8)]);
+        let index = GobIndex::build(&mut Cursor::new(&buf), true).unwrap();
+
+        assert_eq!(index.tags.len(), 6);
+        assert_eq!((index.palette_count(), index.bitmap_count(), index.texture_count(), index.level_count()), (1, 2, 1, 1));
+
+        let second = index.bitmap_entry(1).unwrap();
+        assert_eq!((second.id, second.size), (TAG_BITMAP, 22));
+        assert_eq!(second.offset, (6 + 768) + (6 + 30) + (6 + 112) + 6);
+        assert!(index.bitmap_entry(2).is_none());
+    }
+
+    #[test]
+    fn ignores_bytes_too_short_for_a_header() {
+        let mut buf = gob(&[(TAG_PALETTE, 768)]);
+        buf.extend_from_slice(&[1, 2, 3]);
+
+        let index = GobIndex::build(&mut Cursor::new(&buf), true).unwrap();
+        assert_eq!(index.tags.len(), 1);
+    }
+
+    #[test]
+    fn rejects_tag_smaller_than_its_header() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, TAG_BITMAP, 0).unwrap();
+        buf[2] = 5;
+
+        assert!(GobIndex::build(&mut Cursor::new(&buf), false).is_err());
+    }
+
+    #[test]
+    fn tag_past_end_of_file() {
+        let mut buf = gob(&[(TAG_PALETTE, 768)]);
+        write_header(&mut buf, TAG_BITMAP, 100).unwrap();
+        buf.extend_from_slice(&[0; 40]);
+
+        assert!(GobIndex::build(&mut Cursor::new(&buf), true).is_err());
+
+        let index = GobIndex::build(&mut Cursor::new(&buf), false).unwrap();
+        assert_eq!(index.tags.len(), 1);
+        assert_eq!(index.bitmap_count(), 0);
+    }
+
+    #[test]
+    fn short_read_is_an_error_only_when_strict() {
+        let entry = TagEntry { id: TAG_BITMAP, offset: 6, size: 30 };
+
+        assert!(check_consumed(&entry, 30, true).is_ok());
+        assert!(check_consumed(&entry, 22, false).is_ok());
+        assert!(check_consumed(&entry, 22, true).is_err());
+    }
+
+    #[test]
+    fn decodes_a_bitmap_from_any_reader() {
+        let mut payload = vec![22, 0];
+        payload.extend_from_slice(b"WALL\0\0\0\0\0\0\0\0\0\0");
+        payload.extend_from_slice(&[2, 0, 2, 0, 0, 0, 3, 4, 1, 2]);
+        let mut buf = gob(&[(TAG_PALETTE, 768)]);
+        write_tag(&mut buf, TAG_BITMAP, &payload).unwrap();
+
+        let mut file = Cursor::new(&buf);
+        let index = GobIndex::build(&mut file, true).unwrap();
+        let bmp = index.read_bitmap(&mut file, 0).unwrap();
+        assert_eq!((bmp.width, bmp.height), (2, 2));
+        assert_eq!(&*bmp.top_down(), &[1, 2, 3, 4]);
+        assert!(index.read_bitmap(&mut file, 1).is_err());
+
+        write_tag(&mut buf, TAG_BITMAP, &payload[..24]).unwrap();
+        let mut file = Cursor::new(&buf);
+        let index = GobIndex::build(&mut file, true).unwrap();
+        assert!(index.read_bitmap(&mut file, 1).is_err());
+    }
+}
@@ -0,0 +1,741 @@
+use std::io;
+use std::io::Write;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use ron;
+
+use gob::{read_string, write_string};
+
+/// Marks the missing side of a one-sided wall.
+pub const NO_ZONE: u16 = 0xffff;
+
+/// Fields in the level header, after the leading hdr_size: name, rebuild zone and the
+/// four record counts.
+const LEVEL_HEADER_FIELDS: u16 = 2 + 32 + 2 + 8;
+
+/// Bytes in each vertex, wall, zone and object record.
+const VERTEX_SIZE: usize = 4;
+const WALL_SIZE: usize = 12;
+const ZONE_SIZE: usize = 12;
+const OBJECT_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Vertex {
+    pub x: i16,
+    pub y: i16
+}
+
+/// A wall runs from `v1` to `v2` with `front` on its right. `back` is `NO_ZONE` for a
+/// solid wall. `texture` indexes the GOB's texture list.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wall {
+    pub v1: u16,
+    pub v2: u16,
+    pub front: u16,
+    pub back: u16,
+    pub texture: u16,
+    pub flags: u16
+}
+
+/// A zone's floor and ceiling heights and textures. `light` is the shade table level the
+/// zone is drawn at, 0 being full brightness.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub floor: i16,
+    pub ceiling: i16,
+    pub floor_texture: u16,
+    pub ceiling_texture: u16,
+    pub light: u16,
+    pub flags: u16
+}
+
+/// A placed object. `angle` is a binary angle, 65536 to the full turn, counter-clockwise
+/// from the +x axis.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub kind: u16,
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+    pub angle: u16,
+    pub flags: u16
+}
+
+/// A level from a tag 40 record. VEdit's source isn't to hand, so this layout is a working
+/// hypothesis pieced together from the records themselves, not VEdit's own definition:
+///
+/// ```text
+/// u16      hdr_size          bytes from the start of the record to the vertex list
+/// char[32] name
+/// u16      rebuild_zone
+/// u16      vertex, wall, zone and object counts
+/// ...      rest of the header, up to hdr_size (kept in header_extra)
+/// vertices { i16 x, i16 y }
+/// walls    { u16 v1, v2, front, back, texture, flags }
+/// zones    { i16 floor, ceiling; u16 floor_texture, ceiling_texture, light, flags }
+/// objects  { u16 kind; i16 x, y, z; u16 angle, flags }
+/// ...      anything after the objects (kept in extra)
+/// ```
+///
+/// Bytes the decoder doesn't understand are kept so the record can be written back exactly.
+/// A record that doesn't fit the hypothesis is rejected rather than read as whatever
+/// follows: an odd header size, counts calling for more bytes than it has, or walls with
+/// fewer than two vertices or no zone to face.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub name: String,
+    pub rebuild_zone: u16,
+    pub vertices: Vec<Vertex>,
+    pub walls: Vec<Wall>,
+    pub zones: Vec<Zone>,
+    pub objects: Vec<Object>,
+    pub header_extra: Vec<u8>,
+    pub extra: Vec<u8>
+}
+
+/// Reads a level record of `size` bytes.
+pub fn read_level_data<T: ReadBytesExt>(mut buf: &mut T, size: u32) -> Result<Level, io::Error> {
+    let hdr_size = buf.read_u16::<LittleEndian>()?;
+    if hdr_size < LEVEL_HEADER_FIELDS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("level header size {} is too small", hdr_size)));
+    }
+    if hdr_size as u32 > size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("level header size {} is more than the {} bytes in the tag", hdr_size, size)));
+    }
+    // Every field is 16 bits, so an odd size would leave the records misaligned
+    if hdr_size % 2 != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("level header size {} is odd", hdr_size)));
+    }
+
+    let name = read_string(&mut buf, 32)?;
+    let rebuild_zone = buf.read_u16::<LittleEndian>()?;
+    let vertex_count = buf.read_u16::<LittleEndian>()?;
+    let wall_count = buf.read_u16::<LittleEndian>()?;
+    let zone_count = buf.read_u16::<LittleEndian>()?;
+    let object_count = buf.read_u16::<LittleEndian>()?;
+
+    let needed = hdr_size as usize + vertex_count as usize * VERTEX_SIZE + wall_count as usize * WALL_SIZE +
+        zone_count as usize * ZONE_SIZE + object_count as usize * OBJECT_SIZE;
+    if needed > size as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("level counts ({} vertices, {} walls, {} zones, {} objects) need {} bytes, but the tag has {}",
+                                          vertex_count, wall_count, zone_count, object_count, needed, size)));
+    }
+    // A wall joins two vertices and faces a zone, so walls without them mean the counts
+    // aren't where they were taken to be
+    if wall_count > 0 && (vertex_count < 2 || zone_count == 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("level has {} walls but only {} vertices and {} zones", wall_count, vertex_count, zone_count)));
+    }
+
+    let mut header_extra = vec![0u8; (hdr_size - LEVEL_HEADER_FIELDS) as usize];
+    buf.read_exact(&mut header_extra)?;
+
+    let mut level = Level {
+        name, rebuild_zone,
+        vertices: Vec::with_capacity(vertex_count as usize), walls: Vec::with_capacity(wall_count as usize),
+        zones: Vec::with_capacity(zone_count as usize), objects: Vec::with_capacity(object_count as usize),
+        header_extra, extra: Vec::new()
+    };
+
+    for _ in 0..vertex_count {
+        level.vertices.push(Vertex { x: buf.read_i16::<LittleEndian>()?, y: buf.read_i16::<LittleEndian>()? });
+    }
+
+    for _ in 0..wall_count {
+        level.walls.push(Wall {
+            v1: buf.read_u16::<LittleEndian>()?,
+            v2: buf.read_u16::<LittleEndian>()?,
+            front: buf.read_u16::<LittleEndian>()?,
+            back: buf.read_u16::<LittleEndian>()?,
+            texture: buf.read_u16::<LittleEndian>()?,
+            flags: buf.read_u16::<LittleEndian>()?
+        });
+    }
+
+    for _ in 0..zone_count {
+        level.zones.push(Zone {
+            floor: buf.read_i16::<LittleEndian>()?,
+            ceiling: buf.read_i16::<LittleEndian>()?,
+            floor_texture: buf.read_u16::<LittleEndian>()?,
+            ceiling_texture: buf.read_u16::<LittleEndian>()?,
+            light: buf.read_u16::<LittleEndian>()?,
+            flags: buf.read_u16::<LittleEndian>()?
+        });
+    }
+
+    for _ in 0..object_count {
+        level.objects.push(Object {
+            kind: buf.read_u16::<LittleEndian>()?,
+            x: buf.read_i16::<LittleEndian>()?,
+            y: buf.read_i16::<LittleEndian>()?,
+            z: buf.read_i16::<LittleEndian>()?,
+            angle: buf.read_u16::<LittleEndian>()?,
+            flags: buf.read_u16::<LittleEndian>()?
+        });
+    }
+
+    buf.read_to_end(&mut level.extra)?;
+
+    Ok(level)
+}
+
+/// Writes a level back out in the layout `read_level_data` reads, so a level that was read
+/// and not changed is written back byte for byte.
+pub fn write_level_data<W: WriteBytesExt>(level: &Level, buf: &mut W) -> Result<(), io::Error> {
+    buf.write_u16::<LittleEndian>(LEVEL_HEADER_FIELDS + level.header_extra.len() as u16)?;
+    write_string(buf, &level.name, 32)?;
+    buf.write_u16::<LittleEndian>(level.rebuild_zone)?;
+    buf.write_u16::<LittleEndian>(level.vertices.len() as u16)?;
+    buf.write_u16::<LittleEndian>(level.walls.len() as u16)?;
+    buf.write_u16::<LittleEndian>(level.zones.len() as u16)?;
+    buf.write_u16::<LittleEndian>(level.objects.len() as u16)?;
+    buf.write_all(&level.header_extra)?;
+
+    for v in level.vertices.iter() {
+        buf.write_i16::<LittleEndian>(v.x)?;
+        buf.write_i16::<LittleEndian>(v.y)?;
+    }
+
+    for w in level.walls.iter() {
+        for &f in [w.v1, w.v2, w.front, w.back, w.texture, w.flags].iter() {
+            buf.write_u16::<LittleEndian>(f)?;
+        }
+    }
+
+    for z in level.zones.iter() {
+        buf.write_i16::<LittleEndian>(z.floor)?;
+        buf.write_i16::<LittleEndian>(z.ceiling)?;
+        for &f in [z.floor_texture, z.ceiling_texture, z.light, z.flags].iter() {
+            buf.write_u16::<LittleEndian>(f)?;
+        }
+    }
+
+    for o in level.objects.iter() {
+        buf.write_u16::<LittleEndian>(o.kind)?;
+        buf.write_i16::<LittleEndian>(o.x)?;
+        buf.write_i16::<LittleEndian>(o.y)?;
+        buf.write_i16::<LittleEndian>(o.z)?;
+        buf.write_u16::<LittleEndian>(o.angle)?;
+        buf.write_u16::<LittleEndian>(o.flags)?;
+    }
+
+    buf.write_all(&level.extra)
+}
+
+/// Writes a level as RON laid out for version control: one vertex, wall, zone or object
+/// per line with its index in a comment, fields always in the same order, so an edit
+/// shows up in a diff as just the lines it touched. `read_level_text` reads it back to an
+/// identical `Level`.
+pub fn write_level_text<W: Write>(level: &Level, w: &mut W) -> Result<(), io::Error> {
+    write!(w, "(\n    name: {},\n    rebuild_zone: {},\n", ron_string(&level.name), level.rebuild_zone)?;
+
+    writeln!(w, "    vertices: [")?;
+    for (i, v) in level.vertices.iter().enumerate() {
+        writeln!(w, "        (x: {}, y: {}), // {}", v.x, v.y, i)?;
+    }
+    write!(w, "    ],\n    walls: [\n")?;
+    for (i, l) in level.walls.iter().enumerate() {
+        writeln!(w, "        (v1: {}, v2: {}, front: {}, back: {}, texture: {}, flags: {}), // {}",
+               l.v1, l.v2, l.front, l.back, l.texture, l.flags, i)?;
+    }
+    write!(w, "    ],\n    zones: [\n")?;
+    for (i, z) in level.zones.iter().enumerate() {
+        writeln!(w, "        (floor: {}, ceiling: {}, floor_texture: {}, ceiling_texture: {}, light: {}, flags: {}), // {}",
+               z.floor, z.ceiling, z.floor_texture, z.ceiling_texture, z.light, z.flags, i)?;
+    }
+    write!(w, "    ],\n    objects: [\n")?;
+    for (i, o) in level.objects.iter().enumerate() {
+        writeln!(w, "        (kind: {}, x: {}, y: {}, z: {}, angle: {}, flags: {}), // {}",
+               o.kind, o.x, o.y, o.z, o.angle, o.flags, i)?;
+    }
+    writeln!(w, "    ],")?;
+
+    for &(field, bytes) in [("header_extra", &level.header_extra), ("extra", &level.extra)].iter() {
+        let list: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+        writeln!(w, "    {}: [{}],", field, list.join(", "))?;
+    }
+
+    writeln!(w, ")")
+}
+
+/// A name as a RON string in plain ASCII. Its chars each stand for one byte of the field
+/// (see `read_string`), so anything outside printable ASCII is written as a `\xNN` escape,
+/// which RON reads back as the same char.
+fn ron_string(name: &str) -> String {
+    let mut out = String::from("\"");
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => { out.push('\\'); out.push(c); }
+            ' '..='~' => out.push(c),
+            _ => out.push_str(&format!("\\x{:02x}", c as u32))
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Reads a level written by `write_level_text`, or any RON with the same fields.
+pub fn read_level_text(text: &str) -> Result<Level, io::Error> {
+    ron::de::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl Level {
+    pub fn vertex(&self, idx: u16) -> Option<&Vertex> {
+        self.vertices.get(idx as usize)
+    }
+
+    /// Min and max corners of the level's vertices, or `None` for a level with none.
+    pub fn bounds(&self) -> Option<((i16, i16), (i16, i16))> {
+        if self.vertices.is_empty() {
+            return None;
+        }
+
+        let min_x = self.vertices.iter().map(|v| v.x).min().unwrap();
+        let min_y = self.vertices.iter().map(|v| v.y).min().unwrap();
+        let max_x = self.vertices.iter().map(|v| v.x).max().unwrap();
+        let max_y = self.vertices.iter().map(|v| v.y).max().unwrap();
+
+        Some(((min_x, min_y), (max_x, max_y)))
+    }
+
+    /// Walls bounding `zone`, each as a (from, to) vertex pair oriented so the zone is on
+    /// its right. Walls whose back faces the zone are reversed.
+    pub fn zone_edges(&self, zone: u16) -> Vec<(usize, u16, u16)> {
+        let mut edges = Vec::new();
+        for (i, w) in self.walls.iter().enumerate() {
+            if w.front == zone {
+                edges.push((i, w.v1, w.v2));
+            } else if w.back == zone {
+                edges.push((i, w.v2, w.v1));
+            }
+        }
+
+        edges
+    }
+
+    /// Chains a zone's walls into outlines. Returns the vertex loops and the indices of
+    /// walls that couldn't be joined into a closed loop.
+    pub fn zone_loops(&self, zone: u16) -> (Vec<Vec<u16>>, Vec<usize>) {
+        let mut edges = self.zone_edges(zone);
+        let mut loops = Vec::new();
+        let mut open = Vec::new();
+
+        while let Some((first, start, next)) = edges.pop() {
+            let mut chain = vec![first];
+            let mut outline = vec![start];
+            let mut at = next;
+
+            while at != start {
+                match edges.iter().position(|&(_, from, _)| from == at) {
+                    Some(pos) => {
+                        let (wall, _, to) = edges.remove(pos);
+                        chain.push(wall);
+                        outline.push(at);
+                        at = to;
+                    },
+                    None => break
+                }
+            }
+
+            if at == start {
+                loops.push(outline);
+            } else {
+                open.extend(chain);
+            }
+        }
+
+        (loops, open)
+    }
+
+    /// The average of the vertices around a zone's first outline, if that lands inside
+    /// the zone. Good enough to stand in for a convex or nearly convex zone.
+    pub fn zone_middle(&self, zone: u16) -> Option<(f32, f32)> {
+        let (loops, _) = self.zone_loops(zone);
+        let points: Vec<_> = loops.first()?.iter().filter_map(|&v| self.vertex(v)).collect();
+        if points.is_empty() {
+            return None;
+        }
+        let x = points.iter().map(|v| v.x as f32).sum::<f32>() / points.len() as f32;
+        let y = points.iter().map(|v| v.y as f32).sum::<f32>() / points.len() as f32;
+
+        if self.zone_at(x, y) == Some(zone) { Some((x, y)) } else { None }
+    }
+
+    /// Index of the zone containing the point, if any. Where zones overlap the first wins.
+    pub fn zone_at(&self, x: f32, y: f32) -> Option<u16> {
+        for z in 0..self.zones.len() as u16 {
+            let (loops, _) = self.zone_loops(z);
+            let mut inside = false;
+
+            for outline in loops.iter() {
+                for i in 0..outline.len() {
+                    let (a, b) = match (self.vertex(outline[i]), self.vertex(outline[(i + 1) % outline.len()])) {
+                        (Some(a), Some(b)) => (a, b),
+                        _ => continue
+                    };
+                    let (ax, ay, bx, by) = (a.x as f32, a.y as f32, b.x as f32, b.y as f32);
+                    if (ay > y) != (by > y) && x < (bx - ax) * (y - ay) / (by - ay) + ax {
+                        inside = !inside;
+                    }
+                }
+            }
+
+            if inside {
+                return Some(z);
+            }
+        }
+
+        None
+    }
+
+    /// Splits a zone into convex polygons covering it, as counter-clockwise vertex lists.
+    /// Holes are bridged into their outline, the result is ear clipped and neighbouring
+    /// triangles are then merged wherever the union stays convex. Walls that don't close
+    /// into a loop are ignored.
+    pub fn convex_pieces(&self, zone: u16) -> Vec<Vec<u16>> {
+        let (loops, _) = self.zone_loops(zone);
+        let loops: Vec<Vec<u16>> = loops.into_iter().filter(|l| l.iter().all(|&v| self.vertex(v).is_some())).collect();
+
+        // A loop inside an odd number of others is a hole
+        let depth: Vec<usize> = loops.iter().enumerate().map(|(i, l)| {
+            let (x, y) = self.point(l[0]);
+            let p = (x as f64, y as f64);
+            loops.iter().enumerate().filter(|&(j, other)| j != i && inside(self, other, p)).count()
+        }).collect();
+
+        let mut pieces = Vec::new();
+        for (i, outline) in loops.iter().enumerate().filter(|&(i, _)| depth[i].is_multiple_of(2)) {
+            let mut poly = outline.clone();
+            if self.area(&poly) < 0 {
+                poly.reverse();
+            }
+
+            let mut holes: Vec<Vec<u16>> = loops.iter().enumerate()
+                .filter(|&(j, h)| {
+                    let (x, y) = self.point(h[0]);
+                    depth[j] == depth[i] + 1 && inside(self, outline, (x as f64, y as f64))
+                })
+                .map(|(_, h)| {
+                    let mut h = h.clone();
+                    if self.area(&h) > 0 {
+                        h.reverse();
+                    }
+                    h
+                })
+                .collect();
+            holes.sort_by_key(|h| -h.iter().map(|&v| self.point(v).0).max().unwrap());
+
+            while !holes.is_empty() {
+                let hole = holes.remove(0);
+                poly = self.bridge(poly, &hole, &holes);
+            }
+
+            pieces.extend(merge_convex(self, self.triangulate(poly)));
+        }
+
+        pieces
+    }
+
+    fn point(&self, v: u16) -> (i64, i64) {
+        let v = &self.vertices[v as usize];
+        (v.x as i64, v.y as i64)
+    }
+
+    /// Twice the signed area of a loop, positive when it runs counter-clockwise.
+    fn area(&self, outline: &[u16]) -> i64 {
+        (0..outline.len()).map(|i| {
+            let (a, b) = (self.point(outline[i]), self.point(outline[(i + 1) % outline.len()]));
+            a.0 * b.1 - b.0 * a.1
+        }).sum()
+    }
+
+    /// Joins a clockwise hole into a counter-clockwise outline with a cut from the hole's
+    /// rightmost vertex to the nearest outline vertex it can see.
+    fn bridge(&self, poly: Vec<u16>, hole: &[u16], others: &[Vec<u16>]) -> Vec<u16> {
+        let mi = (0..hole.len()).max_by_key(|&i| self.point(hole[i]).0).unwrap();
+        let m = self.point(hole[mi]);
+
+        let mut candidates: Vec<usize> = (0..poly.len()).collect();
+        candidates.sort_by_key(|&i| {
+            let p = self.point(poly[i]);
+            (p.0 - m.0) * (p.0 - m.0) + (p.1 - m.1) * (p.1 - m.1)
+        });
+
+        let rings: Vec<&[u16]> = Some(&poly[..]).into_iter().chain(Some(hole)).chain(others.iter().map(|h| &h[..])).collect();
+        let visible = |p: (i64, i64)| {
+            let mid = ((p.0 + m.0) as f64 / 2.0, (p.1 + m.1) as f64 / 2.0);
+            rings.iter().all(|ring| (0..ring.len()).all(|i| {
+                let (a, b) = (self.point(ring[i]), self.point(ring[(i + 1) % ring.len()]));
+                !crosses(m, p, a, b)
+            })) && inside(self, &poly, mid) && !inside(self, hole, mid)
+                && others.iter().all(|h| !inside(self, h, mid))
+        };
+
+        let pi = candidates.into_iter().find(|&i| visible(self.point(poly[i]))).unwrap_or(0);
+
+        let mut out = Vec::with_capacity(poly.len() + hole.len() + 2);
+        out.extend_from_slice(&poly[..pi + 1]);
+        out.extend_from_slice(&hole[mi..]);
+        out.extend_from_slice(&hole[..mi + 1]);
+        out.extend_from_slice(&poly[pi..]);
+
+        out
+    }
+
+    /// Ear clips a counter-clockwise polygon, dropping degenerate corners.
+    fn triangulate(&self, mut poly: Vec<u16>) -> Vec<Vec<u16>> {
+        let mut triangles = Vec::new();
+
+        while poly.len() > 3 {
+            let n = poly.len();
+            let mut clipped = false;
+
+            for i in 0..n {
+                let (pv, cv, nv) = (poly[(i + n - 1) % n], poly[i], poly[(i + 1) % n]);
+                let (a, b, c) = (self.point(pv), self.point(cv), self.point(nv));
+                let turn = cross(a, b, c);
+
+                if turn == 0 {
+                    poly.remove(i);
+                    clipped = true;
+                    break;
+                }
+                if turn < 0 {
+                    continue;
+                }
+
+                let blocked = poly.iter().map(|&v| self.point(v))
+                    .any(|p| p != a && p != b && p != c && cross(a, b, p) >= 0 && cross(b, c, p) >= 0 && cross(c, a, p) >= 0);
+                if !blocked {
+                    triangles.push(vec![pv, cv, nv]);
+                    poly.remove(i);
+                    clipped = true;
+                    break;
+                }
+            }
+
+            if !clipped {
+                break;
+            }
+        }
+
+        if poly.len() == 3 && cross(self.point(poly[0]), self.point(poly[1]), self.point(poly[2])) > 0 {
+            triangles.push(poly);
+        }
+
+        triangles
+    }
+}
+
+/// Greedily merges convex polygons that share an edge while the result stays convex.
+fn merge_convex(level: &Level, mut pieces: Vec<Vec<u16>>) -> Vec<Vec<u16>> {
+    'merging: loop {
+        for i in 0..pieces.len() {
+            for j in i + 1..pieces.len() {
+                if let Some(merged) = join(&pieces[i], &pieces[j]) {
+                    let n = merged.len();
+                    let convex = (0..n).all(|k| {
+                        cross(level.point(merged[k]), level.point(merged[(k + 1) % n]), level.point(merged[(k + 2) % n])) > 0
+                    });
+                    if convex {
+                        pieces[i] = merged;
+                        pieces.remove(j);
+                        continue 'merging;
+                    }
+                }
+            }
+        }
+
+        return pieces;
+    }
+}
+
+/// Joins two counter-clockwise polygons along an edge they share, if they share one.
+fn join(a: &[u16], b: &[u16]) -> Option<Vec<u16>> {
+    for i in 0..a.len() {
+        let (from, to) = (a[i], a[(i + 1) % a.len()]);
+        for j in 0..b.len() {
+            if b[j] == to && b[(j + 1) % b.len()] == from {
+                let mut merged: Vec<u16> = (0..a.len()).map(|k| a[(i + 1 + k) % a.len()]).collect();
+                merged.extend((2..b.len()).map(|k| b[(j + k) % b.len()]));
+                return Some(merged);
+            }
+        }
+    }
+
+    None
+}
+
+/// Twice the signed area of triangle o-a-b, positive when it turns counter-clockwise.
+pub fn cross(o: (i64, i64), a: (i64, i64), b: (i64, i64)) -> i64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+/// Whether segments p-q and a-b cross at a point inside both.
+pub fn crosses(p: (i64, i64), q: (i64, i64), a: (i64, i64), b: (i64, i64)) -> bool {
+    if p == a || p == b || q == a || q == b {
+        return false;
+    }
+    let (d1, d2) = (cross(p, q, a), cross(p, q, b));
+    let (d3, d4) = (cross(a, b, p), cross(a, b, q));
+
+    ((d1 > 0 && d2 < 0) || (d1 < 0 && d2 > 0)) && ((d3 > 0 && d4 < 0) || (d3 < 0 && d4 > 0))
+}
+
+/// Even-odd test of a point against a loop whose vertices all exist.
+fn inside(level: &Level, outline: &[u16], p: (f64, f64)) -> bool {
+    let mut inside = false;
+    for i in 0..outline.len() {
+        let (a, b) = (level.point(outline[i]), level.point(outline[(i + 1) % outline.len()]));
+        let (ax, ay, bx, by) = (a.0 as f64, a.1 as f64, b.0 as f64, b.1 as f64);
+        if (ay > p.1) != (by > p.1) && p.0 < (bx - ax) * (p.1 - ay) / (by - ay) + ax {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use gob::clean_name;
+    use super::{read_level_data, read_level_text, write_level_data, write_level_text, Level, Object, Vertex, Wall, Zone, NO_ZONE};
+
+    /// A small level record written out field by field: a triangle of three walls around
+    /// one zone, with one object.
+    const RECORD: &[u8] = &[
+        46, 0,                                              // hdr_size
+        b'E', b'1', b'L', b'1', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,     // name
+        0, 0,                                               // rebuild_zone
+        3, 0, 3, 0, 1, 0, 1, 0,                             // counts
+        0xaa, 0xbb,                                         // rest of the header
+        0, 0, 0, 0,   64, 0, 0, 0,   0, 0, 0xc0, 0xff,      // vertices
+        0, 0, 1, 0, 0, 0, 0xff, 0xff, 2, 0, 0, 0,           // walls
+        1, 0, 2, 0, 0, 0, 0xff, 0xff, 2, 0, 0, 0,
+        2, 0, 0, 0, 0, 0, 0xff, 0xff, 2, 0, 0, 0,
+        0, 0, 128, 0, 1, 0, 1, 0, 3, 0, 0, 0,               // zone
+        7, 0, 16, 0, 0xf0, 0xff, 0, 0, 0, 0x40, 0, 1,       // object
+        1, 2                                                // extra
+    ];
+
+    #[test]
+    fn decodes_a_level_record() {
+        let level = read_level_data(&mut Cursor::new(RECORD), RECORD.len() as u32).unwrap();
+
+        assert_eq!(clean_name(&level.name), "E1L1");
+        assert_eq!(level.header_extra, [0xaa, 0xbb]);
+        assert_eq!(level.vertices, [Vertex { x: 0, y: 0 }, Vertex { x: 64, y: 0 }, Vertex { x: 0, y: -64 }]);
+        assert_eq!(level.walls[1], Wall { v1: 1, v2: 2, front: 0, back: NO_ZONE, texture: 2, flags: 0 });
+        assert_eq!(level.zones, [Zone { floor: 0, ceiling: 128, floor_texture: 1, ceiling_texture: 1, light: 3, flags: 0 }]);
+        assert_eq!(level.objects, [Object { kind: 7, x: 16, y: -16, z: 0, angle: 0x4000, flags: 0x0100 }]);
+        assert_eq!(level.extra, [1, 2]);
+
+        let mut out = Vec::new();
+        write_level_data(&level, &mut out).unwrap();
+        assert_eq!(out, RECORD);
+    }
+
+    #[test]
+    fn rejects_counts_bigger_than_the_tag() {
+        let mut record = RECORD.to_vec();
+        record[38] = 200;
+        assert!(read_level_data(&mut Cursor::new(&record), record.len() as u32).is_err());
+
+        let short = &RECORD[..RECORD.len() - 4];
+        assert!(read_level_data(&mut Cursor::new(short), short.len() as u32).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_headers_and_counts() {
+        let mut odd = RECORD.to_vec();
+        odd[0] = 45;
+        assert!(read_level_data(&mut Cursor::new(&odd), odd.len() as u32).is_err());
+
+        // Walls but no zones: the zone count moved into the object count
+        let mut no_zones = RECORD.to_vec();
+        no_zones[40] = 0;
+        no_zones[42] = 2;
+        assert!(read_level_data(&mut Cursor::new(&no_zones), no_zones.len() as u32).is_err());
+    }
+
+    #[test]
+    fn code_page_names_round_trip_through_text() {
+        // A CP437 name, a quote, and leftovers from an older name after the NUL
+        let mut record = RECORD.to_vec();
+        record[2..15].copy_from_slice(b"CAF\x82 \"1\"\0\xe9old");
+
+        let level = read_level_data(&mut Cursor::new(&record), record.len() as u32).unwrap();
+        let mut text = Vec::new();
+        write_level_text(&level, &mut text).unwrap();
+        assert!(text.is_ascii());
+
+        let back = read_level_text(&String::from_utf8(text).unwrap()).unwrap();
+        assert!(back == level);
+
+        let mut out = Vec::new();
+        write_level_data(&back, &mut out).unwrap();
+        assert_eq!(out, record);
+    }
+
+    /// One zone bounded by each of `loops` in turn.
+    fn zone(loops: &[&[(i16, i16)]]) -> Level {
+        let mut level = Level { name: String::new(), rebuild_zone: 0, vertices: Vec::new(), walls: Vec::new(),
+                                zones: vec![Zone { floor: 0, ceiling: 128, floor_texture: 0, ceiling_texture: 0, light: 0, flags: 0 }],
+                                objects: Vec::new(), header_extra: Vec::new(), extra: Vec::new() };
+        for points in loops {
+            let first = level.vertices.len() as u16;
+            for (i, &(x, y)) in points.iter().enumerate() {
+                level.vertices.push(Vertex { x, y });
+                let next = first + ((i + 1) % points.len()) as u16;
+                level.walls.push(Wall { v1: first + i as u16, v2: next, front: 0, back: NO_ZONE, texture: 0, flags: 0 });
+            }
+        }
+
+        level
+    }
+
+    /// Checks every piece is counter-clockwise and convex, and returns twice their total area.
+    fn convex_area(level: &Level, pieces: &[Vec<u16>]) -> i64 {
+        let mut total = 0;
+        for piece in pieces {
+            let p = |i: usize| { let v = level.vertex(piece[i % piece.len()]).unwrap(); (v.x as i64, v.y as i64) };
+            for i in 0..piece.len() {
+                let (a, b, c) = (p(i), p(i + 1), p(i + 2));
+                assert!((b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0) >= 0, "piece {:?} isn't convex", piece);
+                total += a.0 * b.1 - b.0 * a.1;
+            }
+        }
+
+        total
+    }
+
+    #[test]
+    fn convex_zones_are_one_piece() {
+        // Clockwise, so it has to be turned round
+        let level = zone(&[&[(0, 0), (0, 64), (64, 64), (64, 0)]]);
+        let pieces = level.convex_pieces(0);
+
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].len(), 4);
+        assert_eq!(convex_area(&level, &pieces), 2 * 64 * 64);
+    }
+
+    #[test]
+    fn concave_zones_and_holes_are_split() {
+        let l_shape = zone(&[&[(0, 0), (64, 0), (64, 32), (32, 32), (32, 64), (0, 64)]]);
+        let pieces = l_shape.convex_pieces(0);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(convex_area(&l_shape, &pieces), 2 * 3 * 32 * 32);
+
+        let ring = zone(&[&[(0, 0), (96, 0), (96, 96), (0, 96)], &[(32, 32), (64, 32), (64, 64), (32, 64)]]);
+        let pieces = ring.convex_pieces(0);
+        assert!(pieces.len() >= 4);
+        assert_eq!(convex_area(&ring, &pieces), 2 * (96 * 96 - 32 * 32));
+    }
+}
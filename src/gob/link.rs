@@ -0,0 +1,140 @@
+use std::fmt;
+use std::io;
+
+use gob::{clean_name, Bitmap, Palette, PaletteDepth, RowOrder, Texture};
+use gob::map::Gob;
+
+/// Index of a bitmap that is known to exist in the GOB it was linked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitmapHandle(usize);
+
+/// Index of a palette that is known to exist in the GOB it was linked against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaletteHandle(usize);
+
+impl BitmapHandle {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl PaletteHandle {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A texture whose bitmap and palette indices have been checked against the GOB. A
+/// reference that points past the end of the bitmap or palette list is left as `None`.
+pub struct LinkedTexture {
+    pub texture: Texture,
+    pub bitmap: Option<BitmapHandle>,
+    pub palette: Option<PaletteHandle>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefKind {
+    Bitmap,
+    Palette
+}
+
+/// A texture reference that doesn't resolve to anything in the GOB.
+pub struct DanglingRef {
+    pub texture_idx: usize,
+    pub texture_name: String,
+    pub kind: RefKind,
+    pub index: usize,
+    pub count: usize
+}
+
+impl fmt::Display for DanglingRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            RefKind::Bitmap => "bitmap",
+            RefKind::Palette => "palette"
+        };
+
+        write!(f, "texture {} ({}) refers to {} {}, but the GOB only has {}",
+               self.texture_idx, clean_name(&self.texture_name), kind, self.index, self.count)
+    }
+}
+
+/// Decodes every texture and resolves its bitmap and palette references, collecting the
+/// ones that dangle instead of failing on the first.
+pub fn link(gob: &Gob) -> Result<(Vec<LinkedTexture>, Vec<DanglingRef>), io::Error> {
+    let bitmap_count = gob.index.bitmap_count();
+    let palette_count = gob.index.palette_count();
+
+    let mut linked = Vec::new();
+    let mut dangling = Vec::new();
+
+    for i in 0..gob.index.texture_count() {
+        let t = gob.texture(i)?;
+
+        let bitmap = if t.bitmap_idx < bitmap_count {
+            Some(BitmapHandle(t.bitmap_idx))
+        } else {
+            dangling.push(DanglingRef { texture_idx: i, texture_name: t.name.clone(), kind: RefKind::Bitmap,
+                                        index: t.bitmap_idx, count: bitmap_count });
+            None
+        };
+
+        let palette = if t.colour_idx < palette_count {
+            Some(PaletteHandle(t.colour_idx))
+        } else {
+            dangling.push(DanglingRef { texture_idx: i, texture_name: t.name.clone(), kind: RefKind::Palette,
+                                        index: t.colour_idx, count: palette_count });
+            None
+        };
+
+        linked.push(LinkedTexture { texture: t, bitmap, palette });
+    }
+
+    Ok((linked, dangling))
+}
+
+/// Palette bitmap `idx` is drawn with: that of the first texture using it, or the fallback
+/// if no texture with a palette does.
+pub fn bitmap_palette(gob: &Gob, textures: &[LinkedTexture], idx: usize) -> Result<Palette, io::Error> {
+    let palette = textures.iter()
+        .filter(|t| t.bitmap.map(|h| h.index()) == Some(idx))
+        .filter_map(|t| t.palette)
+        .next();
+
+    match palette {
+        Some(h) => gob.palette(h.index()),
+        None => fallback_palette(gob)
+    }
+}
+
+/// Palette used when a texture's own palette is missing: the GOB's first palette, or a
+/// greyscale ramp if it has none at all.
+pub fn fallback_palette(gob: &Gob) -> Result<Palette, io::Error> {
+    if gob.index.palette_count() > 0 {
+        return gob.palette(0);
+    }
+
+    let mut p = Palette { r: [0; 256], g: [0; 256], b: [0; 256], depth: PaletteDepth::Rgb8 };
+    for i in 0..256 {
+        p.r[i] = i as u8;
+        p.g[i] = i as u8;
+        p.b[i] = i as u8;
+    }
+
+    Ok(p)
+}
+
+/// 16x16 checkerboard of palette indices 0 and 255, standing in for a missing bitmap.
+pub fn placeholder_bitmap(name: &str) -> Bitmap<'static> {
+    let size = 16;
+    let mut data = vec![0u8; size * size];
+    for y in 0..size {
+        for x in 0..size {
+            if (x / 4 + y / 4) % 2 == 0 {
+                data[y * size + x] = 255;
+            }
+        }
+    }
+
+    Bitmap::new(String::from(name), size as u32, size as u32, 0, RowOrder::TopDown, data)
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use gob::edit::RefTarget;
+use gob::level::{cross, crosses, Level};
+use gob::trigger::not_a_trigger_list;
+
+type Point = (i64, i64);
+
+/// Something wrong with a level, found by `check`, at the `index`th `item` (a wall, zone,
+/// object or the level itself).
+#[derive(Serialize)]
+pub struct Issue {
+    pub check: &'static str,
+    pub item: &'static str,
+    pub index: usize,
+    pub message: String
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.item, self.index, self.message)
+    }
+}
+
+fn list(items: &[usize]) -> String {
+    items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Runs every check over a level. `texture_count` is the size of the GOB's texture list.
+///
+/// The geometric checks need every vertex to exist, so they are skipped for a level with
+/// walls referring to missing vertices; those are reported instead.
+pub fn lint(level: &Level, texture_count: usize) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    let dangling = level.dangling(texture_count);
+    let missing_vertices = dangling.iter().any(|d| d.target == RefTarget::Vertex);
+    for d in dangling {
+        issues.push(Issue { check: "dangling", item: d.item, index: d.index,
+                            message: format!("{} refers to {} {}, which doesn't exist", d.field, d.target, d.value) });
+    }
+    if level.triggers().is_none() {
+        issues.push(Issue { check: "undecoded-triggers", item: "level", index: 0,
+                            message: format!("{}, so its triggers weren't checked", not_a_trigger_list(level)) });
+    }
+
+    let mut unclosed = Vec::with_capacity(level.zones.len());
+    for z in 0..level.zones.len() {
+        let (loops, open) = level.zone_loops(z as u16);
+        unclosed.push(!open.is_empty());
+        if loops.is_empty() && open.is_empty() {
+            issues.push(Issue { check: "unclosed-zone", item: "zone", index: z, message: String::from("has no walls") });
+        } else if !open.is_empty() {
+            let mut open = open;
+            open.sort();
+            issues.push(Issue { check: "unclosed-zone", item: "zone", index: z,
+                                message: format!("walls {} don't close into a loop", list(&open)) });
+        }
+    }
+
+    if missing_vertices {
+        return issues;
+    }
+
+    let point = |v: u16| {
+        let v = &level.vertices[v as usize];
+        (v.x as i64, v.y as i64)
+    };
+
+    let mut segments = Vec::new();
+    for (i, w) in level.walls.iter().enumerate() {
+        let (a, b) = (point(w.v1), point(w.v2));
+        if a == b {
+            issues.push(Issue { check: "zero-length-wall", item: "wall", index: i,
+                                message: format!("has zero length, both ends at ({}, {})", a.0, a.1) });
+        } else {
+            segments.push((i, a, b));
+        }
+    }
+
+    for (n, &(i, p, q)) in segments.iter().enumerate() {
+        for &(j, a, b) in segments[n + 1..].iter() {
+            if let Some((check, message)) = conflict(p, q, a, b, j) {
+                issues.push(Issue { check, item: "wall", index: i, message });
+            }
+        }
+    }
+
+    for (i, o) in level.objects.iter().enumerate() {
+        if level.zone_at(o.x as f32, o.y as f32).is_none() {
+            issues.push(Issue { check: "object-outside", item: "object", index: i,
+                                message: format!("at ({}, {}) is outside every zone", o.x, o.y) });
+        }
+    }
+
+    // What's left of an unclosed zone isn't its real shape, so it's left out here
+    let pieces: Vec<Vec<Vec<(i64, i64)>>> = (0..level.zones.len())
+        .map(|z| if unclosed[z] {
+            Vec::new()
+        } else {
+            level.convex_pieces(z as u16).iter().map(|p| p.iter().map(|&v| point(v)).collect()).collect()
+        })
+        .collect();
+    for z in 0..pieces.len() {
+        for other in z + 1..pieces.len() {
+            let overlap = pieces[z].iter().any(|a| pieces[other].iter().any(|b| !separated(a, b) && !separated(b, a)));
+            if overlap {
+                issues.push(Issue { check: "overlapping-zones", item: "zone", index: z, message: format!("overlaps zone {}", other) });
+            }
+        }
+    }
+
+    issues
+}
+
+/// How wall p-q runs into wall `j` from a to b, if it does anything more than share an
+/// end with it, as the check that finds it and a message: crossing it, running along part
+/// of it, or one ending partway along the other.
+fn conflict(p: Point, q: Point, a: Point, b: Point, j: usize) -> Option<(&'static str, String)> {
+    if crosses(p, q, a, b) {
+        return Some(("crossing-walls", format!("crosses wall {}", j)));
+    }
+
+    if cross(p, q, a) == 0 && cross(p, q, b) == 0 {
+        let same = (p == a && q == b) || (p == b && q == a);
+        if same || within(p, q, a) || within(p, q, b) || within(a, b, p) || within(a, b, q) {
+            return Some(("overlapping-walls", format!("overlaps wall {}", j)));
+        }
+    } else if within(a, b, p) || within(a, b, q) {
+        return Some(("t-junction", format!("ends partway along wall {}", j)));
+    } else if within(p, q, a) || within(p, q, b) {
+        return Some(("t-junction", format!("has wall {} ending partway along it", j)));
+    }
+
+    None
+}
+
+/// Whether r lies on segment p-q, short of both ends.
+fn within(p: Point, q: Point, r: Point) -> bool {
+    let dot = |o: Point, a: Point, b: Point| (a.0 - o.0) * (b.0 - o.0) + (a.1 - o.1) * (b.1 - o.1);
+    cross(p, q, r) == 0 && dot(p, q, r) > 0 && dot(q, p, r) > 0
+}
+
+/// Whether an edge of the counter-clockwise convex polygon `a` has all of `b` on or beyond
+/// it. Two convex polygons share some area unless one of them has such an edge.
+fn separated(a: &[Point], b: &[Point]) -> bool {
+    (0..a.len()).any(|i| {
+        let (p, q) = (a[i], a[(i + 1) % a.len()]);
+        b.iter().all(|&r| cross(p, q, r) <= 0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use gob::level::Level;
+    use super::{conflict, lint};
+
+    #[test]
+    fn walls_sharing_an_end_are_fine() {
+        assert_eq!(conflict((0, 0), (64, 0), (64, 0), (64, 64), 1), None);
+        assert_eq!(conflict((0, 0), (64, 0), (64, 0), (128, 0), 1), None);
+    }
+
+    #[test]
+    fn finds_crossing_overlapping_and_t_junction_walls() {
+        let found = |p, q, a, b| conflict(p, q, a, b, 1).unwrap();
+        assert_eq!(found((0, 0), (64, 64), (0, 64), (64, 0)), ("crossing-walls", String::from("crosses wall 1")));
+        assert_eq!(found((0, 0), (64, 0), (32, 0), (96, 0)), ("overlapping-walls", String::from("overlaps wall 1")));
+        assert_eq!(found((0, 0), (64, 0), (64, 0), (0, 0)), ("overlapping-walls", String::from("overlaps wall 1")));
+        assert_eq!(found((32, 0), (32, 64), (0, 0), (64, 0)), ("t-junction", String::from("ends partway along wall 1")));
+        assert_eq!(found((0, 0), (64, 0), (32, 0), (32, 64)),
+                   ("t-junction", String::from("has wall 1 ending partway along it")));
+    }
+
+    #[test]
+    fn reports_extra_bytes_that_are_not_triggers() {
+        let mut level = Level { name: String::from("E1L1"), rebuild_zone: 0, vertices: Vec::new(), walls: Vec::new(),
+                                zones: Vec::new(), objects: Vec::new(), header_extra: Vec::new(), extra: vec![0, 0] };
+        assert!(!lint(&level, 0).iter().any(|i| i.check == "undecoded-triggers"));
+
+        level.extra = vec![1, 2, 3];
+        assert!(lint(&level, 0).iter().any(|i| i.check == "undecoded-triggers"));
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io;
+use std::io::Cursor;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use gob::{Palette, Bitmap, BitmapHeader, RowOrder, Texture, Level, BITMAP_HEADER_SIZE};
+use gob::{read_string, read_bitmap_header};
+use gob::shade::{ShadeTable, check_tag_id, read_shade_data};
+use gob::index::{GobIndex, TagEntry, lookup, check_consumed, overrun};
+
+/// A GOB mapped into memory. Tags are decoded through the index's `Read + Seek` path over
+/// the mapping, except bitmaps, which borrow their pixels from it without copying them.
+pub struct Gob {
+    map: Mmap,
+    pub index: GobIndex
+}
+
+impl Gob {
+    pub fn open<P: AsRef<Path>>(path: P, strict: bool) -> Result<Gob, io::Error> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is only sound while nothing else changes the file. Nothing
+        // here writes to it, but another program truncating or rewriting it while we run
+        // could make reads fault or see torn data; that's accepted for a tool run over
+        // game files, and bounds are still checked against the length mapped here.
+        let map = unsafe { Mmap::map(&file)? };
+        let index = GobIndex::build(&mut Cursor::new(&map[..]), strict)?;
+
+        Ok(Gob { map, index })
+    }
+
+    /// Raw payload of a tag, without its header.
+    pub fn tag_data(&self, entry: &TagEntry) -> Result<&[u8], io::Error> {
+        let start = entry.offset as usize;
+        let end = start + entry.size as usize;
+        if end > self.map.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      format!("tag {} at offset {} runs past the end of the file", entry.id, start)));
+        }
+
+        Ok(&self.map[start..end])
+    }
+
+    /// Bytes after the last whole tag, too short to be one.
+    pub fn trailing(&self) -> &[u8] {
+        let end = self.index.tags.last().map_or(0, |t| t.offset as usize + t.size as usize);
+        &self.map[end.min(self.map.len())..]
+    }
+
+    pub fn print_file_info(&self) -> Result<(), io::Error> {
+        self.index.print_file_info(&mut self.reader())
+    }
+
+    pub fn palette(&self, idx: usize) -> Result<Palette, io::Error> {
+        self.index.read_palette(&mut self.reader(), idx)
+    }
+
+    /// Decodes a bitmap, borrowing its bottom-up rows from the mapping.
+    pub fn bitmap(&self, idx: usize) -> Result<Bitmap<'_>, io::Error> {
+        let (hdr, raw) = self.bitmap_tag(idx)?;
+        Ok(Bitmap::new(hdr.name, hdr.width, hdr.height, hdr.flags, RowOrder::BottomUp, raw))
+    }
+
+    /// Just the fixed fields of a bitmap, without touching its pixels.
+    pub fn bitmap_header(&self, idx: usize) -> Result<BitmapHeader, io::Error> {
+        self.bitmap_tag(idx).map(|(hdr, _)| hdr)
+    }
+
+    fn bitmap_tag(&self, idx: usize) -> Result<(BitmapHeader, &[u8]), io::Error> {
+        let entry = lookup(self.index.bitmap_entry(idx), "bitmap", idx)?;
+        let data = self.tag_data(entry)?;
+        let hdr = read_bitmap_header(&mut Cursor::new(data)).map_err(|e| overrun(entry, e))?;
+
+        let end = BITMAP_HEADER_SIZE + (hdr.width * hdr.height) as usize;
+        if end > data.len() {
+            return Err(overrun(entry, io::Error::new(io::ErrorKind::UnexpectedEof, "bitmap pixel data")));
+        }
+        check_consumed(entry, end as u64, self.index.strict)?;
+
+        Ok((hdr, &data[BITMAP_HEADER_SIZE..end]))
+    }
+
+    pub fn texture(&self, idx: usize) -> Result<Texture, io::Error> {
+        self.index.read_texture(&mut self.reader(), idx)
+    }
+
+    /// Just a level's name, without decoding the rest of it.
+    pub fn level_name(&self, idx: usize) -> Result<String, io::Error> {
+        let entry = lookup(self.index.level_entry(idx), "level", idx)?;
+        let mut data = Cursor::new(self.tag_data(entry)?);
+        data.set_position(2);
+
+        read_string(&mut data, 32).map_err(|e| overrun(entry, e))
+    }
+
+    pub fn level(&self, idx: usize) -> Result<Level, io::Error> {
+        self.index.read_level(&mut self.reader(), idx)
+    }
+
+    /// Every shade table in tags with this id. Textures pick theirs by colour index; one
+    /// whose index is out of range gets a warning, and is left to the caller's fallback.
+    pub fn shade_tables(&self, id: u16) -> Result<Vec<ShadeTable>, io::Error> {
+        check_tag_id(id)?;
+
+        let mut tables = Vec::new();
+        for entry in self.index.tags.iter().filter(|t| t.id == id) {
+            tables.push(self.index.decode(&mut self.reader(), entry, |b| read_shade_data(b, entry.size))?);
+        }
+        if tables.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no tags with id {}", id)));
+        }
+
+        for t in 0..self.index.texture_count() {
+            let colour_idx = self.texture(t)?.colour_idx;
+            if colour_idx >= tables.len() {
+                eprintln!("warning: texture {} has colour index {}, but there are only {} shade tables", t, colour_idx, tables.len());
+            }
+        }
+
+        Ok(tables)
+    }
+
+    /// The whole mapping as a reader, for the index to decode tags from.
+    fn reader(&self) -> Cursor<&[u8]> {
+        Cursor::new(&self.map[..])
+    }
+}
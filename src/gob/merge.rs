@@ -0,0 +1,275 @@
+use std::fmt;
+use std::io;
+use std::io::{Cursor, Write};
+
+use gob::{clean_name, read_string, write_texture_data, write_level_data};
+use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_LEVEL};
+use gob::index::TagEntry;
+use gob::map::Gob;
+use gob::write::write_tag;
+
+/// Set on a bitmap, palette or texture index in a patch to say it refers to that entry of
+/// the base, with any earlier patches merged in, rather than of the patch's own list.
+pub const BASE_REF: u16 = 0x8000;
+
+/// Something a patch put into the merged GOB, at `index` in its kind's list.
+pub struct Overlay {
+    pub kind: &'static str,
+    pub name: String,
+    pub index: usize,
+    pub replaced: bool
+}
+
+impl fmt::Display for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} ({})", if self.replaced { "replaced" } else { "added" }, self.kind, self.name, self.index)
+    }
+}
+
+/// A GOB assembled from a base and its patches, as (id, payload) tags in file order.
+pub struct Merged {
+    pub tags: Vec<(u16, Vec<u8>)>,
+    pub overlays: Vec<Overlay>,
+    /// Patch tags that aren't palettes, bitmaps, textures or levels, which are left out,
+    /// with the index of the patch each came from.
+    pub ignored: Vec<(usize, TagEntry)>
+}
+
+impl Merged {
+    pub fn write<W: Write>(&self, buf: &mut W) -> Result<(), io::Error> {
+        for &(id, ref payload) in self.tags.iter() {
+            write_tag(buf, id, payload)?;
+        }
+
+        Ok(())
+    }
+
+    fn slots(&self, id: u16) -> Vec<usize> {
+        self.tags.iter().enumerate().filter(|&(_, t)| t.0 == id).map(|(i, _)| i).collect()
+    }
+
+    /// Puts a payload in the `idx`th tag of its kind, or after the last one when `idx` is
+    /// `None` or past the end. Returns where it went in the kind's list.
+    fn put(&mut self, id: u16, idx: Option<usize>, payload: Vec<u8>) -> (usize, bool) {
+        let slots = self.slots(id);
+        match idx.and_then(|i| slots.get(i).map(|&s| (i, s))) {
+            Some((i, slot)) => {
+                self.tags[slot].1 = payload;
+                (i, true)
+            },
+            None => {
+                let at = slots.last().map_or(self.tags.len(), |&s| s + 1);
+                self.tags.insert(at, (id, payload));
+                (slots.len(), false)
+            }
+        }
+    }
+
+    /// Position in its kind's list of the first tag with this name.
+    fn find(&self, id: u16, name: &str) -> Option<usize> {
+        self.slots(id).iter().position(|&s| tag_name(id, &self.tags[s].1) == name)
+    }
+}
+
+/// Where a reference from patch `p` ends up in the merged `kind` list. Flagged with
+/// `BASE_REF`, it's an index into the `base_count` entries the base and earlier patches
+/// have, kept as is; otherwise it's an index into the patch's own list, which `own` maps to
+/// where each one went. Anything else is an error rather than a guess at which was meant.
+fn resolve(p: usize, what: &str, kind: &str, idx: u16, own: &[usize], base_count: usize) -> Result<u16, io::Error> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("patch {}: {} {}", p, what, msg));
+
+    if idx & BASE_REF != 0 {
+        let base = idx & !BASE_REF;
+        if base as usize >= base_count {
+            return Err(invalid(format!("refers to base {} {}, but there are only {}", kind, base, base_count)));
+        }
+        return Ok(base);
+    }
+
+    match own.get(idx as usize) {
+        Some(&i) => Ok(i as u16),
+        None => Err(invalid(format!("refers to {} {}, but the patch only has {}; add {:#x} to refer to the base's {} {}",
+                                    kind, idx, own.len(), BASE_REF, kind, idx)))
+    }
+}
+
+/// Clean name of a bitmap, texture or level tag, read straight from its payload.
+fn tag_name(id: u16, payload: &[u8]) -> String {
+    let (offset, len) = match id {
+        TAG_BITMAP => (2, 14),
+        TAG_TEXTURE => (0, 32),
+        TAG_LEVEL => (2, 32),
+        _ => return String::new()
+    };
+    if payload.len() < offset + len {
+        return String::new();
+    }
+
+    read_string(&mut Cursor::new(&payload[offset..]), len).map(|n| String::from(clean_name(&n))).unwrap_or_default()
+}
+
+/// Overlays each patch onto the base in turn. Palettes replace the base's by index, and
+/// bitmaps, textures and levels replace the first of the same name, with anything new
+/// added after the last of its kind so no existing index moves. The patch's textures and
+/// levels are remapped to where its bitmaps, palettes and textures ended up. To use one of
+/// the base's instead, a patch flags the index with `BASE_REF`; an unflagged index past
+/// the end of the patch's own list is an error. Tags that remapping leaves as they were
+/// are copied byte for byte.
+pub fn merge(base: &Gob, patches: &[Gob]) -> Result<Merged, io::Error> {
+    let mut merged = Merged { tags: Vec::new(), overlays: Vec::new(), ignored: Vec::new() };
+    for entry in base.index.tags.iter() {
+        merged.tags.push((entry.id, base.tag_data(entry)?.to_vec()));
+    }
+
+    for (p, patch) in patches.iter().enumerate() {
+        merged.ignored.extend(patch.index.tags.iter()
+            .filter(|t| t.id != TAG_PALETTE && t.id != TAG_BITMAP && t.id != TAG_TEXTURE && t.id != TAG_LEVEL)
+            .map(|&t| (p, t)));
+        let base_count = |id: u16| merged.slots(id).len();
+        let (base_palettes, base_bitmaps, base_textures) = (base_count(TAG_PALETTE), base_count(TAG_BITMAP), base_count(TAG_TEXTURE));
+
+        let mut palettes = Vec::new();
+        for (k, entry) in patch.index.tags.iter().filter(|t| t.id == TAG_PALETTE).enumerate() {
+            let (idx, replaced) = merged.put(TAG_PALETTE, Some(k), patch.tag_data(entry)?.to_vec());
+            merged.overlays.push(Overlay { kind: "palette", name: format!("palette_{}", k), index: idx, replaced });
+            palettes.push(idx);
+        }
+
+        let mut bitmaps = Vec::new();
+        for entry in patch.index.tags.iter().filter(|t| t.id == TAG_BITMAP) {
+            let payload = patch.tag_data(entry)?.to_vec();
+            let name = tag_name(TAG_BITMAP, &payload);
+            let existing = merged.find(TAG_BITMAP, &name);
+            let (idx, replaced) = merged.put(TAG_BITMAP, existing, payload);
+            merged.overlays.push(Overlay { kind: "bitmap", name, index: idx, replaced });
+            bitmaps.push(idx);
+        }
+
+        let mut textures = Vec::new();
+        for i in 0..patch.index.texture_count() {
+            let mut t = patch.texture(i)?;
+            let original = (t.bitmap_idx, t.colour_idx);
+            let what = format!("texture {}", clean_name(&t.name));
+            t.bitmap_idx = resolve(p, &what, "bitmap", t.bitmap_idx as u16, &bitmaps, base_bitmaps)? as usize;
+            t.colour_idx = resolve(p, &what, "palette", t.colour_idx as u16, &palettes, base_palettes)? as usize;
+
+            let payload = if (t.bitmap_idx, t.colour_idx) == original {
+                patch.tag_data(patch.index.texture_entry(i).unwrap())?.to_vec()
+            } else {
+                let mut payload = Vec::new();
+                write_texture_data(&t, &mut payload)?;
+                payload
+            };
+            let name = String::from(clean_name(&t.name));
+            let existing = merged.find(TAG_TEXTURE, &name);
+            let (idx, replaced) = merged.put(TAG_TEXTURE, existing, payload);
+            merged.overlays.push(Overlay { kind: "texture", name, index: idx, replaced });
+            textures.push(idx);
+        }
+
+        for i in 0..patch.index.level_count() {
+            let original = patch.level(i)?;
+            let mut level = original.clone();
+            let name = clean_name(&level.name);
+            let remap = |what: String, t: u16| resolve(p, &what, "texture", t, &textures, base_textures);
+            for (j, w) in level.walls.iter_mut().enumerate() {
+                w.texture = remap(format!("level {} wall {}", name, j), w.texture)?;
+            }
+            for (j, z) in level.zones.iter_mut().enumerate() {
+                z.floor_texture = remap(format!("level {} zone {}", name, j), z.floor_texture)?;
+                z.ceiling_texture = remap(format!("level {} zone {}", name, j), z.ceiling_texture)?;
+            }
+
+            let payload = if level == original {
+                patch.tag_data(patch.index.level_entry(i).unwrap())?.to_vec()
+            } else {
+                let mut payload = Vec::new();
+                write_level_data(&level, &mut payload)?;
+                payload
+            };
+            let name = String::from(clean_name(&level.name));
+            let existing = merged.find(TAG_LEVEL, &name);
+            let (idx, replaced) = merged.put(TAG_LEVEL, existing, payload);
+            merged.overlays.push(Overlay { kind: "level", name, index: idx, replaced });
+        }
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::{Cursor, Write};
+    use std::path::Path;
+
+    use gob::{read_texture_data, write_string, write_texture_data, Texture, TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE};
+    use gob::map::Gob;
+    use gob::write::write_tag;
+    use super::{merge, BASE_REF};
+
+    fn bitmap(out: &mut Vec<u8>, name: &str) {
+        let mut payload = vec![22, 0];
+        write_string(&mut payload, name, 14).unwrap();
+        payload.extend_from_slice(&[1, 0, 1, 0, 0, 0, 7]);
+        write_tag(out, TAG_BITMAP, &payload).unwrap();
+    }
+
+    fn texture(out: &mut Vec<u8>, name: &str, bitmap_idx: usize, colour_idx: usize) {
+        let mut payload = Vec::new();
+        write_texture_data(&Texture { name: String::from(name), bitmap_idx, colour_idx, pixel_size: 1.0 }, &mut payload).unwrap();
+        write_tag(out, TAG_TEXTURE, &payload).unwrap();
+    }
+
+    fn open(dir: &Path, name: &str, data: &[u8]) -> Gob {
+        File::create(dir.join(name)).unwrap().write_all(data).unwrap();
+        Gob::open(dir.join(name), true).unwrap()
+    }
+
+    /// Base: a palette, bitmaps A and B, and texture TA on A. Patch: bitmap C, then
+    /// `textures` on the base's palette.
+    fn merge_textures(test: &str, textures: &[(&str, usize)]) -> Result<Vec<Texture>, String> {
+        let dir = env::temp_dir().join(format!("cabal-merge-test-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut base = Vec::new();
+        write_tag(&mut base, TAG_PALETTE, &[0; 768]).unwrap();
+        bitmap(&mut base, "A");
+        bitmap(&mut base, "B");
+        texture(&mut base, "TA", 0, 0);
+        let mut patch = Vec::new();
+        bitmap(&mut patch, "C");
+        for &(name, idx) in textures {
+            texture(&mut patch, name, idx, BASE_REF as usize);
+        }
+
+        let merged = merge(&open(&dir, "base.gob", &base), &[open(&dir, "patch.gob", &patch)]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let merged = merged.map_err(|e| e.to_string())?;
+        Ok(merged.tags.iter().filter(|t| t.0 == TAG_TEXTURE).map(|t| read_texture_data(&mut Cursor::new(&t.1)).unwrap()).collect())
+    }
+
+    #[test]
+    fn patch_indices_follow_the_patch_bitmaps() {
+        // C is the patch's bitmap 0, and lands after A and B
+        let textures = merge_textures("own", &[("TC", 0)]).unwrap();
+        assert_eq!(textures.iter().map(|t| (&t.name[..2], t.bitmap_idx, t.colour_idx)).collect::<Vec<_>>(),
+                   [("TA", 0, 0), ("TC", 2, 0)]);
+    }
+
+    #[test]
+    fn flagged_indices_refer_to_the_base() {
+        let textures = merge_textures("base", &[("TB", BASE_REF as usize | 1), ("TA", 0)]).unwrap();
+        assert_eq!(textures.iter().map(|t| (&t.name[..2], t.bitmap_idx)).collect::<Vec<_>>(), [("TA", 2), ("TB", 1)]);
+    }
+
+    #[test]
+    fn unflagged_indices_past_the_patch_are_errors() {
+        let e = merge_textures("past", &[("TB", 1)]).err().unwrap();
+        assert!(e.contains("texture TB refers to bitmap 1, but the patch only has 1"), "{}", e);
+        assert!(merge_textures("missing", &[("TB", BASE_REF as usize | 2)]).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+pub mod catalogue;
+pub mod diff;
+pub mod edit;
+pub mod index;
+pub mod level;
+pub mod link;
+pub mod lint;
+pub mod merge;
+pub mod map;
+pub mod music;
+pub mod shade;
+pub mod sound;
+pub mod stats;
+pub mod trigger;
+pub mod write;
+
+use std::borrow::Cow;
+use std::io;
+use std::io::Write;
+use std::string::String;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+pub use self::level::{Level, read_level_data, write_level_data, read_level_text, write_level_text};
+
+pub const TAG_PALETTE: u16 = 2;
+pub const TAG_BITMAP: u16 = 14;
+pub const TAG_TEXTURE: u16 = 15;
+pub const TAG_FILE_INFO: u16 = 20;
+pub const TAG_OLD_LEVEL: u16 = 33;
+pub const TAG_LEVEL: u16 = 40;
+
+/// Size of the (id, tag_size) header in front of every tag.
+pub const TAG_HEADER_SIZE: u32 = 6;
+
+pub struct Texture {
+    pub name: String,
+    pub bitmap_idx: usize,
+    pub colour_idx: usize,
+    pub pixel_size: f32
+}
+
+/// Size of the fixed header at the start of a bitmap tag, before the pixel data.
+pub const BITMAP_HEADER_SIZE: usize = 22;
+
+/// Order of the rows in a bitmap's `data`. VEdit stores rows bottom-up like a Windows
+/// DIB; bitmaps built in memory are top-down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RowOrder {
+    TopDown,
+    BottomUp
+}
+
+/// Pixels are palette indices in row-major order, `width` to a row, with the rows kept in
+/// whichever order they came in. A bitmap decoded from a mapped GOB borrows its bottom-up
+/// rows as they are; `pixel` and `row` count rows from the top either way, and writers ask
+/// for the order their format wants with `top_down` or `bottom_up`, which only copy when
+/// the rows are stored the other way round.
+pub struct Bitmap<'a> {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub flags: u16,
+    rows: RowOrder,
+    data: Cow<'a, [u8]>
+}
+
+/// The fixed fields at the start of a bitmap tag.
+pub struct BitmapHeader {
+    pub hdr_size: u16,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub flags: u16
+}
+
+impl<'a> Bitmap<'a> {
+    /// A bitmap of `width * height` pixels from `data`, whose rows are in `rows` order.
+    pub fn new<D: Into<Cow<'a, [u8]>>>(name: String, width: u32, height: u32, flags: u16, rows: RowOrder, data: D) -> Bitmap<'a> {
+        Bitmap { name, width, height, flags, rows, data: data.into() }
+    }
+
+    /// Where row `y`, counting from the top, starts in `data`.
+    fn row_start(&self, y: u32) -> usize {
+        let stored = match self.rows {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => self.height - 1 - y
+        };
+        (stored * self.width) as usize
+    }
+
+    /// Row `y`, counting from the top.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = self.row_start(y);
+        &self.data[start..start + self.width as usize]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        self.data[self.row_start(y) + x as usize]
+    }
+
+    /// Every pixel with row 0 at the top. This stands in for a `pixels()` slice: the rows
+    /// aren't always stored top-down, so there's no single order to hand out a reference
+    /// in, and this borrows whenever they are.
+    pub fn top_down(&self) -> Cow<'_, [u8]> {
+        self.in_order(RowOrder::TopDown)
+    }
+
+    /// Every pixel with row 0 at the bottom, as stored in the GOB.
+    pub fn bottom_up(&self) -> Cow<'_, [u8]> {
+        self.in_order(RowOrder::BottomUp)
+    }
+
+    fn in_order(&self, rows: RowOrder) -> Cow<'_, [u8]> {
+        if self.rows == rows {
+            Cow::Borrowed(&self.data)
+        } else {
+            Cow::Owned(flip_rows(&self.data, self.width, self.height))
+        }
+    }
+}
+
+/// Bit depth of the colour components as stored in the GOB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteDepth {
+    /// 0-63 VGA DAC values.
+    Vga6,
+    Rgb8
+}
+
+impl PaletteDepth {
+    pub fn parse(s: &str) -> Option<PaletteDepth> {
+        match s {
+            "6" => Some(PaletteDepth::Vga6),
+            "8" => Some(PaletteDepth::Rgb8),
+            _ => None
+        }
+    }
+}
+
+/// Colour components are always 8 bit once decoded; `depth` records how they were stored
+/// so the palette can be written back the same way.
+pub struct Palette {
+    pub r: [u8; 256],
+    pub g: [u8; 256],
+    pub b: [u8; 256],
+    pub depth: PaletteDepth
+}
+
+impl Palette {
+    /// Interleaved RGB triples, as most image formats store palettes.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(768);
+        for i in 0..256 {
+            out.push(self.r[i]);
+            out.push(self.g[i]);
+            out.push(self.b[i]);
+        }
+
+        out
+    }
+}
+
+
+/// Reads a fixed-width field of `len` bytes. Names are in the DOS code page rather than
+/// UTF-8, so each byte becomes the char with the same value (U+0000 to U+00FF) and
+/// `write_string` gives back exactly the bytes read, NULs and anything after them included.
+pub fn read_string<T: ReadBytesExt>(buf: &mut T, len: usize) -> Result<String, io::Error> {
+    let mut str = vec![0u8; len];
+    buf.read_exact(&mut str)?;
+
+    Ok(str.iter().map(|&b| b as char).collect())
+}
+
+/// Writes `s` as a fixed-width field of `len` bytes, NUL padded, one byte per char. Fails
+/// on a char above U+00FF or a string too long for the field rather than cutting it short.
+pub fn write_string<W: Write>(buf: &mut W, s: &str, len: usize) -> Result<(), io::Error> {
+    let mut out = vec![0u8; len];
+    for (i, c) in s.chars().enumerate() {
+        if i >= len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is longer than {} bytes", s, len)));
+        }
+        if c as u32 > 0xff {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} has {:?}, which isn't a single byte", s, c)));
+        }
+        out[i] = c as u8;
+    }
+
+    buf.write_all(&out)
+}
+
+/// Names are fixed-width and NUL padded; this gives just the name itself.
+pub fn clean_name(name: &str) -> &str {
+    // Only ASCII spaces: U+0085 and U+00A0 are code page letters here, not whitespace
+    name.split('\0').next().unwrap_or("").trim_matches(|c: char| c.is_ascii_whitespace())
+}
+
+/// Keeps a name to characters that are safe in a file name on any system.
+pub fn file_stem(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+pub fn read_header<T: ReadBytesExt>(buf: &mut T) -> Result<(u16, u32), io::Error> {
+    let id = buf.read_u16::<LittleEndian>()?;
+    let tag_size = buf.read_u32::<LittleEndian>()?;
+
+    Ok((id, tag_size))
+}
+
+pub fn read_file_info<T: ReadBytesExt>(mut buf: &mut T) -> Result<(), io::Error> {
+    println!("GOB Info:");
+    println!(" -- Name: {}", read_string(&mut buf, 32)?);
+    println!(" -- Author: {}", read_string(&mut buf, 32)?);
+    println!(" -- Date: {}", read_string(&mut buf, 32)?);
+    println!(" -- VEDIT Version: {}", read_string(&mut buf, 16)?);
+
+    Ok(())
+}
+
+/// Reads a palette, expanding 6 bit VGA values to 8 bits. With no `depth` given, a palette
+/// whose components all fit in 0-63 is taken to be 6 bit.
+pub fn read_palette_data<T: ReadBytesExt>(buf: &mut T, depth: Option<PaletteDepth>) -> Result<Palette, io::Error> {
+    let mut p = Palette { r: [0; 256], g: [0; 256], b: [0; 256], depth: PaletteDepth::Rgb8 };
+
+    buf.read_exact(&mut p.r)?;
+    buf.read_exact(&mut p.g)?;
+    buf.read_exact(&mut p.b)?;
+
+    let max = p.r.iter().chain(p.g.iter()).chain(p.b.iter()).cloned().max().unwrap_or(0);
+    p.depth = depth.unwrap_or(if max <= 63 { PaletteDepth::Vga6 } else { PaletteDepth::Rgb8 });
+
+    if p.depth == PaletteDepth::Vga6 {
+        for c in p.r.iter_mut().chain(p.g.iter_mut()).chain(p.b.iter_mut()) {
+            let v = *c & 0x3f;
+            *c = (v << 2) | (v >> 4);
+        }
+    }
+
+    Ok(p)
+}
+
+/// Writes a palette tag payload, in the depth it was originally stored in.
+pub fn write_palette_data<W: Write>(p: &Palette, buf: &mut W) -> Result<(), io::Error> {
+    for channel in [&p.r, &p.g, &p.b].iter() {
+        let mut out = **channel;
+        if p.depth == PaletteDepth::Vga6 {
+            for c in out.iter_mut() {
+                *c >>= 2;
+            }
+        }
+        buf.write_all(&out)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_bitmap_header<T: ReadBytesExt>(mut buf: &mut T) -> Result<BitmapHeader, io::Error> {
+    let hdr_size = buf.read_u16::<LittleEndian>()?;
+    let name = read_string(&mut buf, 14)?;
+    let x_len = buf.read_u16::<LittleEndian>()? as u32;
+    let y_len = buf.read_u16::<LittleEndian>()? as u32;
+    let flags = buf.read_u16::<LittleEndian>()?;
+
+    Ok(BitmapHeader { hdr_size, name, width: x_len, height: y_len, flags })
+}
+
+/// Reads a bitmap tag payload into a bitmap that owns its pixels, left bottom-up as stored.
+pub fn read_bitmap_data<T: ReadBytesExt>(mut buf: &mut T) -> Result<Bitmap<'static>, io::Error> {
+    let hdr = read_bitmap_header(&mut buf)?;
+
+    let mut data = vec![0u8; (hdr.width * hdr.height) as usize];
+    buf.read_exact(&mut data)?;
+
+    Ok(Bitmap::new(hdr.name, hdr.width, hdr.height, hdr.flags, RowOrder::BottomUp, data))
+}
+
+/// Writes a bitmap tag payload, with its rows bottom-up. `hdr_size` is the
+/// header's leading size field, which nothing reads but is kept as found.
+pub fn write_bitmap_data<W: Write>(b: &Bitmap, hdr_size: u16, buf: &mut W) -> Result<(), io::Error> {
+    buf.write_u16::<LittleEndian>(hdr_size)?;
+    write_string(buf, &b.name, 14)?;
+    buf.write_u16::<LittleEndian>(b.width as u16)?;
+    buf.write_u16::<LittleEndian>(b.height as u16)?;
+    buf.write_u16::<LittleEndian>(b.flags)?;
+    buf.write_all(&b.bottom_up())
+}
+
+/// Reverses the row order of a row-major image, converting between the bottom-up layout
+/// in the GOB and top-down.
+pub fn flip_rows(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    if width == 0 {
+        return out;
+    }
+
+    for row in data.chunks(width as usize).take(height as usize).rev() {
+        out.extend_from_slice(row);
+    }
+
+    out
+}
+
+pub fn read_texture_data<T: ReadBytesExt>(mut buf: &mut T) -> Result<Texture, io::Error> {
+    let mut texture = Texture { name: String::from(""), bitmap_idx: 0, colour_idx: 0, pixel_size: 0.0 };
+    texture.name = read_string(&mut buf, 32)?;
+    texture.bitmap_idx = buf.read_u16::<LittleEndian>()? as usize;
+    texture.colour_idx = buf.read_u16::<LittleEndian>()? as usize;
+    texture.pixel_size = buf.read_f32::<LittleEndian>()?;
+
+    Ok(texture)
+}
+
+pub fn write_texture_data<W: Write>(t: &Texture, buf: &mut W) -> Result<(), io::Error> {
+    write_string(buf, &t.name, 32)?;
+    buf.write_u16::<LittleEndian>(t.bitmap_idx as u16)?;
+    buf.write_u16::<LittleEndian>(t.colour_idx as u16)?;
+    buf.write_f32::<LittleEndian>(t.pixel_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::io::Cursor;
+
+    use super::{clean_name, read_string, write_string, Bitmap, RowOrder};
+
+    #[test]
+    fn bottom_up_bitmap_borrows_and_counts_rows_from_the_top() {
+        let stored = [4u8, 5, 6, 1, 2, 3];
+        let bmp = Bitmap::new(String::new(), 3, 2, 0, RowOrder::BottomUp, &stored[..]);
+
+        assert_eq!(bmp.row(0), &[1, 2, 3]);
+        assert_eq!(bmp.pixel(2, 1), 6);
+        assert!(match bmp.bottom_up() { Cow::Borrowed(d) => d == &stored[..], Cow::Owned(_) => false });
+        assert_eq!(&*bmp.top_down(), &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn string_fields() {
+        let mut buf = Vec::new();
+        write_string(&mut buf, "E1L1", 8).unwrap();
+        assert_eq!(buf, b"E1L1\0\0\0\0");
+
+        let name = read_string(&mut Cursor::new(&buf), 8).unwrap();
+        assert_eq!(clean_name(&name), "E1L1");
+    }
+
+    #[test]
+    fn string_fields_keep_every_byte() {
+        let field = b"CAF\x82\0\xe9junk";
+        let name = read_string(&mut Cursor::new(&field[..]), 10).unwrap();
+        assert_eq!(clean_name(&name), "CAF\u{82}");
+
+        let mut buf = Vec::new();
+        write_string(&mut buf, &name, 10).unwrap();
+        assert_eq!(buf, &field[..]);
+    }
+
+    #[test]
+    fn string_too_long_for_its_field_is_an_error() {
+        assert!(write_string(&mut Vec::new(), "E1L1", 3).is_err());
+        assert!(write_string(&mut Vec::new(), "\u{2500}", 8).is_err());
+    }
+
+    #[test]
+    fn short_string_field_is_an_error() {
+        assert!(read_string(&mut Cursor::new(b"E1L1"), 8).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::io;
+
+use gob::map::Gob;
+use gob::sound;
+
+/// How far into a tag to look for the start of an embedded music file.
+const MAX_PREFIX: usize = 64;
+
+/// Native formats a music tag may hold, recognised by their signatures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MusicFormat {
+    /// Standard MIDI File.
+    Midi,
+    /// DMX MUS, as used by id's games and their licensees.
+    Mus,
+    /// Creative Music File.
+    Cmf,
+    /// Miles extended MIDI.
+    Xmi,
+    Mod,
+    S3m,
+    Xm
+}
+
+impl MusicFormat {
+    /// File extension for the format as it's stored natively.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            MusicFormat::Midi => "mid",
+            MusicFormat::Mus => "mus",
+            MusicFormat::Cmf => "cmf",
+            MusicFormat::Xmi => "xmi",
+            MusicFormat::Mod => "mod",
+            MusicFormat::S3m => "s3m",
+            MusicFormat::Xm => "xm"
+        }
+    }
+}
+
+/// A music track. `data` is the embedded file exactly as stored, starting at its
+/// signature.
+pub struct MusicTrack {
+    pub name: String,
+    pub format: MusicFormat,
+    pub data: Vec<u8>
+}
+
+/// Looks for a known music file near the start of a tag, returning its format and offset.
+pub fn detect(payload: &[u8]) -> Option<(MusicFormat, usize)> {
+    if payload.len() >= 48 && &payload[44..48] == b"SCRM" {
+        return Some((MusicFormat::S3m, 0));
+    }
+    if payload.len() >= 1084 {
+        match &payload[1080..1084] {
+            b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" | b"6CHN" | b"8CHN" => return Some((MusicFormat::Mod, 0)),
+            _ => {}
+        }
+    }
+
+    let end = payload.len().min(MAX_PREFIX + 1);
+    for i in 0..end {
+        let rest = &payload[i..];
+        if rest.starts_with(b"MThd") {
+            return Some((MusicFormat::Midi, i));
+        }
+        if rest.starts_with(b"MUS\x1a") {
+            return Some((MusicFormat::Mus, i));
+        }
+        if rest.starts_with(b"CTMF") {
+            return Some((MusicFormat::Cmf, i));
+        }
+        if rest.starts_with(b"FORM") && rest.len() >= 12 && &rest[8..12] == b"XDIR" {
+            return Some((MusicFormat::Xmi, i));
+        }
+        if rest.starts_with(b"Extended Module: ") {
+            return Some((MusicFormat::Xm, i));
+        }
+    }
+
+    None
+}
+
+/// Every music track in the GOB, with the position of its tag in `gob.index.tags`.
+pub fn find_music(gob: &Gob) -> Result<Vec<(usize, MusicTrack)>, io::Error> {
+    let mut tracks = Vec::new();
+
+    for (i, entry) in gob.index.tags.iter().enumerate() {
+        if sound::is_known_tag(entry.id) {
+            continue;
+        }
+
+        let payload = gob.tag_data(entry)?;
+        if sound::detect(payload).is_some() {
+            continue;
+        }
+
+        if let Some((format, offset)) = detect(payload) {
+            tracks.push((i, MusicTrack {
+                name: format!("music_{}", tracks.len()),
+                format,
+                data: payload[offset..].to_vec()
+            }));
+        }
+    }
+
+    Ok(tracks)
+}
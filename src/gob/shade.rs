@@ -0,0 +1,127 @@
+use std::io;
+
+use byteorder::ReadBytesExt;
+
+use gob::{Bitmap, Palette, RowOrder};
+use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_FILE_INFO, TAG_OLD_LEVEL, TAG_LEVEL};
+
+/// Most light levels a shade table tag is taken to hold. Anything bigger is more likely
+/// some other data that happens to be a multiple of 256 bytes.
+pub const MAX_SHADE_LEVELS: u32 = 64;
+
+/// Which tag id holds shade tables isn't known, so `--shade-tag` names it, but these ids
+/// are known to hold something else.
+const KNOWN_TAGS: [(u16, &str); 6] = [(TAG_PALETTE, "palettes"), (TAG_BITMAP, "bitmaps"), (TAG_TEXTURE, "textures"),
+                                               (TAG_FILE_INFO, "file info"), (TAG_OLD_LEVEL, "old levels"), (TAG_LEVEL, "levels")];
+
+/// Fails if tags with this id are known to hold something other than shade tables.
+pub fn check_tag_id(id: u16) -> Result<(), io::Error> {
+    match KNOWN_TAGS.iter().find(|&&(known, _)| known == id) {
+        Some(&(_, what)) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                               format!("tag id {} holds {}, not shade tables", id, what))),
+        None => Ok(())
+    }
+}
+
+/// Light level remapping tables. `levels[0]` is full brightness and each following level
+/// is darker; `levels[l][i]` is the palette index colour `i` becomes at light level `l`.
+pub struct ShadeTable {
+    pub levels: Vec<[u8; 256]>
+}
+
+impl ShadeTable {
+    /// Builds a table by darkening every colour towards black in `count` even steps and
+    /// picking the nearest palette entry, for GOBs that don't carry their own tables.
+    pub fn build(palette: &Palette, count: usize) -> ShadeTable {
+        let mut levels = Vec::with_capacity(count);
+
+        for l in 0..count {
+            let scale = 1.0 - (l as f32 / count as f32);
+            let mut table = [0u8; 256];
+            for (i, entry) in table.iter_mut().enumerate() {
+                let r = palette.r[i] as f32 * scale;
+                let g = palette.g[i] as f32 * scale;
+                let b = palette.b[i] as f32 * scale;
+                *entry = nearest(palette, r, g, b);
+            }
+            levels.push(table);
+        }
+
+        ShadeTable { levels }
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns a copy of `bmp` as it appears at light level `level`.
+    pub fn apply(&self, bmp: &Bitmap, level: usize) -> Bitmap<'static> {
+        let table = &self.levels[level];
+        let data: Vec<u8> = bmp.top_down().iter().map(|&p| table[p as usize]).collect();
+
+        Bitmap::new(bmp.name.clone(), bmp.width, bmp.height, bmp.flags, RowOrder::TopDown, data)
+    }
+}
+
+/// Reads a shade table tag: `size` bytes of consecutive 256 entry tables, brightest first,
+/// at least two and at most `MAX_SHADE_LEVELS` of them.
+pub fn read_shade_data<T: ReadBytesExt>(buf: &mut T, size: u32) -> Result<ShadeTable, io::Error> {
+    if size == 0 || !size.is_multiple_of(256) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("shade table size {} is not a multiple of 256", size)));
+    }
+    if size / 256 < 2 || size / 256 > MAX_SHADE_LEVELS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("shade table size {} gives {} light levels, not 2 to {}", size, size / 256, MAX_SHADE_LEVELS)));
+    }
+
+    let mut levels = Vec::new();
+    for _ in 0..size / 256 {
+        let mut table = [0u8; 256];
+        buf.read_exact(&mut table)?;
+        levels.push(table);
+    }
+
+    Ok(ShadeTable { levels })
+}
+
+fn nearest(palette: &Palette, r: f32, g: f32, b: f32) -> u8 {
+    let mut best = 0;
+    let mut best_dist = f32::MAX;
+
+    for i in 0..256 {
+        let dr = palette.r[i] as f32 - r;
+        let dg = palette.g[i] as f32 - g;
+        let db = palette.b[i] as f32 - b;
+        let dist = dr * dr + dg * dg + db * db;
+        if dist < best_dist {
+            best = i;
+            best_dist = dist;
+        }
+    }
+
+    best as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use gob::TAG_PALETTE;
+    use super::{check_tag_id, read_shade_data};
+
+    #[test]
+    fn refuses_known_tag_ids() {
+        assert!(check_tag_id(TAG_PALETTE).is_err());
+        assert!(check_tag_id(50).is_ok());
+    }
+
+    #[test]
+    fn table_sizes() {
+        let data = vec![7u8; 256 * 80];
+        assert_eq!(read_shade_data(&mut Cursor::new(&data), 256 * 16).unwrap().level_count(), 16);
+        assert!(read_shade_data(&mut Cursor::new(&data), 256).is_err());
+        assert!(read_shade_data(&mut Cursor::new(&data), 768 + 1).is_err());
+        assert!(read_shade_data(&mut Cursor::new(&data), 256 * 80).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use std::io;
+use std::io::{Cursor, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use gob::file_stem;
+use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_FILE_INFO, TAG_OLD_LEVEL, TAG_LEVEL};
+use gob::map::Gob;
+use gob::music;
+
+const VOC_MAGIC: &[u8] = b"Creative Voice File\x1a";
+const VOC_HEADER_SIZE: u16 = 26;
+const VOC_VERSION: u16 = 0x010a;
+
+/// How far into a tag to look for the start of an embedded VOC or WAV file.
+const MAX_PREFIX: usize = 64;
+
+/// How a sound's samples were wrapped inside its tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundContainer {
+    Voc,
+    Wav,
+    Raw
+}
+
+/// Sample layout assumed for tags holding bare PCM, which carry no header of their own.
+#[derive(Clone, Copy, Debug)]
+pub struct RawFormat {
+    pub sample_rate: u32,
+    pub bits: u16,
+    pub signed: bool
+}
+
+impl Default for RawFormat {
+    fn default() -> RawFormat {
+        RawFormat { sample_rate: 11025, bits: 8, signed: false }
+    }
+}
+
+/// A digitised sound effect. `data` holds interleaved little-endian samples of `bits`
+/// each. `container` and `prefix` (any bytes in the tag ahead of the sound itself) are
+/// kept so the sound can be written back into a GOB the way it came out.
+pub struct Sound {
+    pub name: String,
+    pub sample_rate: u32,
+    pub bits: u16,
+    pub signed: bool,
+    pub channels: u16,
+    pub data: Vec<u8>,
+    pub container: SoundContainer,
+    pub prefix: Vec<u8>
+}
+
+impl Sound {
+    /// Samples converted to WAV's convention: unsigned when 8 bit, signed when 16 bit.
+    pub fn wav_samples(&self) -> Vec<u8> {
+        convert_sign(&self.data, self.bits, self.signed, self.bits != 8)
+    }
+
+    /// Replaces the samples with ones read from a WAV file. Signedness is kept as it was
+    /// unless the sample size changes, in which case WAV's convention is taken. Bare PCM
+    /// has no header to record a new layout in, so for those the WAV has to match the
+    /// sound's rate, sample size and channel count.
+    pub fn replace_from_wav<R: Read>(&mut self, buf: &mut R) -> Result<(), io::Error> {
+        let wav = read_wav(buf)?;
+        if self.container == SoundContainer::Raw &&
+            (wav.sample_rate, wav.bits, wav.channels) != (self.sample_rate, self.bits, self.channels) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("{} is stored without a header as {} Hz, {} bit, {} channel samples, but the WAV is {} Hz, {} bit, {} channel",
+                                              self.name, self.sample_rate, self.bits, self.channels, wav.sample_rate, wav.bits, wav.channels)));
+        }
+
+        let signed = if wav.bits == self.bits { self.signed } else { wav.signed };
+
+        self.data = convert_sign(&wav.data, wav.bits, wav.signed, signed);
+        self.signed = signed;
+        self.sample_rate = wav.sample_rate;
+        self.bits = wav.bits;
+        self.channels = wav.channels;
+
+        Ok(())
+    }
+}
+
+/// Flips the sign convention of the samples if `from` and `to` differ.
+fn convert_sign(data: &[u8], bits: u16, from: bool, to: bool) -> Vec<u8> {
+    let mut out = data.to_vec();
+    if from != to {
+        let step = (bits / 8).max(1) as usize;
+        for i in (step - 1..out.len()).step_by(step) {
+            out[i] ^= 0x80;
+        }
+    }
+
+    out
+}
+
+/// Looks for an embedded VOC or WAV file near the start of a tag, returning its
+/// container and offset.
+pub fn detect(payload: &[u8]) -> Option<(SoundContainer, usize)> {
+    let end = payload.len().min(MAX_PREFIX + VOC_MAGIC.len());
+
+    for i in 0..end {
+        let rest = &payload[i..];
+        if rest.starts_with(VOC_MAGIC) {
+            return Some((SoundContainer::Voc, i));
+        }
+        if rest.len() >= 12 && &rest[0..4] == b"RIFF" && &rest[8..12] == b"WAVE" {
+            return Some((SoundContainer::Wav, i));
+        }
+    }
+
+    None
+}
+
+/// Decodes a sound tag payload. Tags without a recognisable container are read as bare
+/// PCM in the `raw` layout.
+pub fn decode_sound(name: &str, payload: &[u8], raw: RawFormat) -> Result<Sound, io::Error> {
+    let (container, offset) = detect(payload).unwrap_or((SoundContainer::Raw, 0));
+    let body = &payload[offset..];
+
+    let mut sound = match container {
+        SoundContainer::Voc => read_voc(body)?,
+        SoundContainer::Wav => read_wav(&mut Cursor::new(body))?,
+        SoundContainer::Raw => Sound {
+            name: String::new(), sample_rate: raw.sample_rate, bits: raw.bits, signed: raw.signed, channels: 1,
+            data: body.to_vec(), container: SoundContainer::Raw, prefix: Vec::new()
+        }
+    };
+    sound.name = String::from(name);
+    sound.prefix = payload[..offset].to_vec();
+
+    Ok(sound)
+}
+
+/// Builds the tag payload for a sound, in the container it was read from.
+pub fn encode_sound(sound: &Sound) -> Result<Vec<u8>, io::Error> {
+    let mut out = sound.prefix.clone();
+
+    match sound.container {
+        SoundContainer::Voc => write_voc(sound, &mut out)?,
+        SoundContainer::Wav => write_wav(sound, &mut out)?,
+        SoundContainer::Raw => out.extend_from_slice(&sound.data)
+    }
+
+    Ok(out)
+}
+
+/// Ids of the tags holding an embedded VOC or WAV file, in order of first appearance.
+pub fn sound_tag_ids(gob: &Gob) -> Result<Vec<u16>, io::Error> {
+    let mut ids = Vec::new();
+    for entry in gob.index.tags.iter().filter(|t| !is_known_tag(t.id)) {
+        if !ids.contains(&entry.id) && detect(gob.tag_data(entry)?).is_some() {
+            ids.push(entry.id);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Whether tags with this id are known to hold something other than sound or music.
+pub fn is_known_tag(id: u16) -> bool {
+    matches!(id, TAG_PALETTE | TAG_BITMAP | TAG_TEXTURE | TAG_FILE_INFO | TAG_OLD_LEVEL | TAG_LEVEL)
+}
+
+/// The name stored ahead of an embedded sound: the bytes before its container, if they
+/// start with a NUL terminated run of printable characters. Anything that can't be used
+/// in a file name is replaced with `_`.
+pub fn prefix_name(prefix: &[u8]) -> Option<String> {
+    let end = prefix.iter().position(|&b| b == 0)?;
+    let name = &prefix[..end];
+    if name.is_empty() || !name.iter().all(|&b| b.is_ascii_graphic() || b == b' ') {
+        return None;
+    }
+
+    let name = String::from_utf8_lossy(name);
+    Some(file_stem(name.trim()))
+}
+
+/// Every sound in the GOB, with the position of its tag in `gob.index.tags`. Tags that
+/// aren't otherwise understood are searched for embedded VOC/WAV files, and any other
+/// tag with the same id as one that has one is a sound too. Those without a container,
+/// and every tag with id `raw_tag`, are taken as bare PCM in the `raw` layout unless they
+/// hold music.
+///
+/// A sound is named by the name stored ahead of it where there is one, and `sound_<n>`
+/// otherwise, with `_<n>` added to any name already taken.
+pub fn find_sounds(gob: &Gob, raw_tag: Option<u16>, raw: RawFormat) -> Result<Vec<(usize, Sound)>, io::Error> {
+    let sound_ids = sound_tag_ids(gob)?;
+    let mut sounds: Vec<(usize, Sound)> = Vec::new();
+
+    for (i, entry) in gob.index.tags.iter().enumerate() {
+        if is_known_tag(entry.id) {
+            continue;
+        }
+
+        let payload = gob.tag_data(entry)?;
+        let found = detect(payload);
+        let is_sound = found.is_some() ||
+            ((sound_ids.contains(&entry.id) || Some(entry.id) == raw_tag) && music::detect(payload).is_none());
+        if !is_sound {
+            continue;
+        }
+
+        let mut sound = decode_sound("", payload, raw)?;
+        let mut name = prefix_name(&sound.prefix).unwrap_or_else(|| format!("sound_{}", sounds.len()));
+        if sounds.iter().any(|(_, s)| s.name == name) {
+            name = format!("{}_{}", name, sounds.len());
+        }
+        sound.name = name;
+        sounds.push((i, sound));
+    }
+
+    Ok(sounds)
+}
+
+/// VOC block sizes are 24 bit little-endian.
+fn read_u24<R: Read>(buf: &mut R) -> Result<u32, io::Error> {
+    let mut b = [0u8; 3];
+    buf.read_exact(&mut b)?;
+
+    Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16)
+}
+
+fn write_u24<W: Write>(buf: &mut W, v: u32) -> Result<(), io::Error> {
+    buf.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8])
+}
+
+pub fn read_voc(data: &[u8]) -> Result<Sound, io::Error> {
+    let mut buf = Cursor::new(data);
+    buf.set_position(VOC_MAGIC.len() as u64);
+    let header_size = buf.read_u16::<LittleEndian>()?;
+    buf.set_position(header_size as u64);
+
+    let mut sound = Sound {
+        name: String::new(), sample_rate: 0, bits: 8, signed: false, channels: 1,
+        data: Vec::new(), container: SoundContainer::Voc, prefix: Vec::new()
+    };
+
+    loop {
+        let block = match buf.read_u8() {
+            Ok(0) | Err(_) => break,
+            Ok(b) => b
+        };
+        let size = read_u24(&mut buf)? as usize;
+        let start = buf.position() as usize;
+        if start + size > data.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "VOC block runs past the end of the sound"));
+        }
+
+        match block {
+            1 if size >= 2 => {
+                let divisor = buf.read_u8()?;
+                let codec = buf.read_u8()?;
+                if codec != 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported VOC codec {}", codec)));
+                }
+                sound.sample_rate = 1000000 / (256 - divisor as u32);
+                sound.data.extend_from_slice(&data[start + 2..start + size]);
+            },
+            2 => sound.data.extend_from_slice(&data[start..start + size]),
+            9 if size >= 12 => {
+                sound.sample_rate = buf.read_u32::<LittleEndian>()?;
+                sound.bits = buf.read_u8()? as u16;
+                sound.channels = buf.read_u8()? as u16;
+                let codec = buf.read_u16::<LittleEndian>()?;
+                sound.signed = match codec {
+                    0 => false,
+                    4 => true,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported VOC codec {}", codec)))
+                };
+                sound.data.extend_from_slice(&data[start + 12..start + size]);
+            },
+            _ => {}
+        }
+
+        buf.set_position((start + size) as u64);
+    }
+
+    Ok(sound)
+}
+
+/// Writes a VOC file. 8 bit unsigned mono sound uses the original block type 1 that DOS
+/// players expect; anything else needs the newer type 9.
+pub fn write_voc<W: Write>(sound: &Sound, buf: &mut W) -> Result<(), io::Error> {
+    buf.write_all(VOC_MAGIC)?;
+    buf.write_u16::<LittleEndian>(VOC_HEADER_SIZE)?;
+    buf.write_u16::<LittleEndian>(VOC_VERSION)?;
+    buf.write_u16::<LittleEndian>((!VOC_VERSION).wrapping_add(0x1234))?;
+
+    if sound.bits == 8 && !sound.signed && sound.channels == 1 && sound.sample_rate > 3906 {
+        buf.write_u8(1)?;
+        write_u24(buf, sound.data.len() as u32 + 2)?;
+        buf.write_u8((256 - 1000000 / sound.sample_rate) as u8)?;
+        buf.write_u8(0)?;
+    } else {
+        buf.write_u8(9)?;
+        write_u24(buf, sound.data.len() as u32 + 12)?;
+        buf.write_u32::<LittleEndian>(sound.sample_rate)?;
+        buf.write_u8(sound.bits as u8)?;
+        buf.write_u8(sound.channels as u8)?;
+        buf.write_u16::<LittleEndian>(if sound.signed { 4 } else { 0 })?;
+        buf.write_u32::<LittleEndian>(0)?;
+    }
+    buf.write_all(&sound.data)?;
+    buf.write_u8(0)?;
+
+    Ok(())
+}
+
+/// Reads a PCM WAV file. The samples keep WAV's sign convention, which `signed` records.
+pub fn read_wav<R: Read>(buf: &mut R) -> Result<Sound, io::Error> {
+    let mut riff = [0u8; 12];
+    buf.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WAV file"));
+    }
+
+    let mut sound = Sound {
+        name: String::new(), sample_rate: 0, bits: 0, signed: false, channels: 0,
+        data: Vec::new(), container: SoundContainer::Wav, prefix: Vec::new()
+    };
+
+    loop {
+        let mut id = [0u8; 4];
+        if buf.read_exact(&mut id).is_err() {
+            break;
+        }
+        let size = buf.read_u32::<LittleEndian>()? as usize;
+        // Read through `take` rather than into a buffer of `size` so a bad size can't
+        // allocate more than the file holds
+        let mut chunk = Vec::new();
+        buf.by_ref().take(size as u64).read_to_end(&mut chunk)?;
+        if chunk.len() < size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "WAV chunk runs past the end of the file"));
+        }
+
+        // Odd-sized chunks should be followed by a pad byte, but some writers leave it off
+        // the last one
+        if size % 2 == 1 {
+            let mut pad = [0u8; 1];
+            if let Err(e) = buf.read_exact(&mut pad) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    return Err(e);
+                }
+            }
+        }
+
+        match &id {
+            b"fmt " => {
+                let mut fmt = Cursor::new(&chunk);
+                let format = fmt.read_u16::<LittleEndian>()?;
+                if format != 1 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported WAV format {}", format)));
+                }
+                sound.channels = fmt.read_u16::<LittleEndian>()?;
+                sound.sample_rate = fmt.read_u32::<LittleEndian>()?;
+                let _byte_rate = fmt.read_u32::<LittleEndian>()?;
+                let _align = fmt.read_u16::<LittleEndian>()?;
+                sound.bits = fmt.read_u16::<LittleEndian>()?;
+                sound.signed = sound.bits != 8;
+            },
+            b"data" => {
+                sound.data = chunk;
+                break;
+            },
+            _ => {}
+        }
+    }
+
+    if sound.bits == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "WAV file has no fmt chunk"));
+    }
+
+    Ok(sound)
+}
+
+pub fn write_wav<W: Write>(sound: &Sound, buf: &mut W) -> Result<(), io::Error> {
+    let samples = sound.wav_samples();
+    let align = sound.channels * sound.bits.div_ceil(8);
+    let pad = samples.len() % 2;
+
+    buf.write_all(b"RIFF")?;
+    buf.write_u32::<LittleEndian>((4 + 8 + 16 + 8 + samples.len() + pad) as u32)?;
+    buf.write_all(b"WAVE")?;
+
+    buf.write_all(b"fmt ")?;
+    buf.write_u32::<LittleEndian>(16)?;
+    buf.write_u16::<LittleEndian>(1)?;
+    buf.write_u16::<LittleEndian>(sound.channels)?;
+    buf.write_u32::<LittleEndian>(sound.sample_rate)?;
+    buf.write_u32::<LittleEndian>(sound.sample_rate * align as u32)?;
+    buf.write_u16::<LittleEndian>(align)?;
+    buf.write_u16::<LittleEndian>(sound.bits)?;
+
+    buf.write_all(b"data")?;
+    buf.write_u32::<LittleEndian>(samples.len() as u32)?;
+    buf.write_all(&samples)?;
+    if pad == 1 {
+        buf.write_u8(0)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Cursor;
+
+    use super::{decode_sound, prefix_name, read_wav, write_wav, RawFormat, SoundContainer};
+
+    fn wav(samples: &[u8]) -> Vec<u8> {
+        let mut sound = decode_sound("", samples, RawFormat::default()).unwrap();
+        sound.container = SoundContainer::Wav;
+        let mut out = Vec::new();
+        write_wav(&sound, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let data = wav(&[1, 2, 3, 4]);
+        let sound = read_wav(&mut Cursor::new(&data)).unwrap();
+        assert_eq!((sound.sample_rate, sound.bits, sound.channels), (11025, 8, 1));
+        assert_eq!(sound.data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn pad_byte_is_optional_at_end_of_file() {
+        let mut data = wav(&[1, 2, 3]);
+        assert_eq!(data.pop(), Some(0));
+
+        let sound = read_wav(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(sound.data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn chunk_sizes_past_the_end_are_rejected() {
+        let mut data = wav(&[1, 2, 3, 4]);
+        let len = data.len();
+        data[len - 8..len - 4].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f]);
+
+        let e = read_wav(&mut Cursor::new(&data)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn bare_samples_only_take_a_wav_of_the_same_layout() {
+        let mut sound = decode_sound("", &[1, 2, 3], RawFormat::default()).unwrap();
+        assert_eq!(sound.container, SoundContainer::Raw);
+
+        assert!(sound.replace_from_wav(&mut Cursor::new(wav(&[4, 5]))).is_ok());
+        assert_eq!(sound.data, [4, 5]);
+
+        let mut other = decode_sound("", &[4, 5], RawFormat { sample_rate: 22050, ..RawFormat::default() }).unwrap();
+        other.container = SoundContainer::Wav;
+        let mut data = Vec::new();
+        write_wav(&other, &mut data).unwrap();
+        assert!(sound.replace_from_wav(&mut Cursor::new(data)).is_err());
+        assert_eq!(sound.data, [4, 5]);
+    }
+
+    #[test]
+    fn names_ahead_of_the_sound() {
+        assert_eq!(prefix_name(b"DOOR OPEN\0\0\0"), Some(String::from("DOOR_OPEN")));
+        assert_eq!(prefix_name(b"\x01\x02\0"), None);
+        assert_eq!(prefix_name(b"NOEND"), None);
+        assert_eq!(prefix_name(b""), None);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeSet;
+
+use gob::Level;
+use gob::link::LinkedTexture;
+
+/// Counts and extents of one level, and the textures it refers to.
+pub struct LevelStats {
+    pub name: String,
+    pub vertices: usize,
+    pub walls: usize,
+    pub zones: usize,
+    pub objects: usize,
+    /// `None` where the level's extra bytes aren't a trigger list.
+    pub triggers: Option<usize>,
+    pub bounds: Option<((i16, i16), (i16, i16))>,
+    /// Every texture index used by a wall, floor or ceiling, whether or not it exists.
+    pub textures: BTreeSet<usize>
+}
+
+impl LevelStats {
+    pub fn new(level: &Level) -> LevelStats {
+        let mut textures = BTreeSet::new();
+        textures.extend(level.walls.iter().map(|w| w.texture as usize));
+        textures.extend(level.zones.iter().flat_map(|z| vec![z.floor_texture as usize, z.ceiling_texture as usize]));
+
+        LevelStats {
+            name: level.name.clone(),
+            vertices: level.vertices.len(),
+            walls: level.walls.len(),
+            zones: level.zones.len(),
+            objects: level.objects.len(),
+            triggers: level.triggers().map(|t| t.len()),
+            bounds: level.bounds(),
+            textures
+        }
+    }
+}
+
+/// Who refers to what across the GOB: the levels using each texture and the textures
+/// using each bitmap. An empty list marks an orphan.
+pub struct Usage {
+    pub texture_levels: Vec<Vec<usize>>,
+    pub bitmap_textures: Vec<Vec<usize>>
+}
+
+impl Usage {
+    pub fn new(bitmap_count: usize, textures: &[LinkedTexture], levels: &[LevelStats]) -> Usage {
+        let mut texture_levels = vec![Vec::new(); textures.len()];
+        for (l, stats) in levels.iter().enumerate() {
+            for &t in stats.textures.iter() {
+                if let Some(users) = texture_levels.get_mut(t) {
+                    users.push(l);
+                }
+            }
+        }
+
+        let mut bitmap_textures = vec![Vec::new(); bitmap_count];
+        for (t, linked) in textures.iter().enumerate() {
+            if let Some(h) = linked.bitmap {
+                bitmap_textures[h.index()].push(t);
+            }
+        }
+
+        Usage { texture_levels, bitmap_textures }
+    }
+
+    /// Levels that use a bitmap through any of its textures.
+    pub fn bitmap_levels(&self, bitmap: usize) -> Vec<usize> {
+        let levels: BTreeSet<usize> = self.bitmap_textures[bitmap].iter()
+            .flat_map(|&t| self.texture_levels[t].iter().cloned())
+            .collect();
+
+        levels.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
+    use gob::{write_string, write_texture_data, Texture, TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE};
+    use gob::level::{Level, Vertex, Wall, Zone, NO_ZONE};
+    use gob::link::{self, LinkedTexture};
+    use gob::map::Gob;
+    use gob::write::write_tag;
+    use super::{LevelStats, Usage};
+
+    /// Links a GOB of bitmaps A and B and textures T0 and T1 on A and T2 on a bitmap that
+    /// isn't there.
+    fn textures() -> Vec<LinkedTexture> {
+        let mut data = Vec::new();
+        write_tag(&mut data, TAG_PALETTE, &[0; 768]).unwrap();
+        for name in ["A", "B"].iter() {
+            let mut payload = vec![22, 0];
+            write_string(&mut payload, name, 14).unwrap();
+            payload.extend_from_slice(&[1, 0, 1, 0, 0, 0, 7]);
+            write_tag(&mut data, TAG_BITMAP, &payload).unwrap();
+        }
+        for &(name, bitmap_idx) in [("T0", 0), ("T1", 0), ("T2", 9)].iter() {
+            let mut payload = Vec::new();
+            write_texture_data(&Texture { name: String::from(name), bitmap_idx, colour_idx: 0, pixel_size: 1.0 }, &mut payload).unwrap();
+            write_tag(&mut data, TAG_TEXTURE, &payload).unwrap();
+        }
+
+        let dir = env::temp_dir().join(format!("cabal-stats-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        File::create(dir.join("test.gob")).unwrap().write_all(&data).unwrap();
+        let (textures, _) = link::link(&Gob::open(dir.join("test.gob"), true).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        textures
+    }
+
+    /// L0: a triangle walled in T0 with T1 underfoot and a dangling ceiling texture, and
+    /// one trigger. L1: no walls, a floor and ceiling of T1, and extra bytes that aren't
+    /// triggers.
+    fn levels() -> Vec<Level> {
+        let zone = |floor_texture, ceiling_texture| Zone { floor: 0, ceiling: 64, floor_texture, ceiling_texture, light: 0, flags: 0 };
+
+        let l0 = Level {
+            name: String::from("L0"),
+            rebuild_zone: 0,
+            vertices: vec![Vertex { x: -8, y: 0 }, Vertex { x: 64, y: 0 }, Vertex { x: 0, y: -32 }],
+            walls: (0..3).map(|i| Wall { v1: i, v2: (i + 1) % 3, front: 0, back: NO_ZONE, texture: 0, flags: 0 }).collect(),
+            zones: vec![zone(1, 7)],
+            objects: Vec::new(),
+            header_extra: Vec::new(),
+            extra: vec![1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 64, 0, 1, 0, 0, 0]
+        };
+        let l1 = Level {
+            name: String::from("L1"),
+            vertices: Vec::new(),
+            walls: Vec::new(),
+            zones: vec![zone(1, 1)],
+            extra: vec![1, 2, 3],
+            ..l0.clone()
+        };
+
+        vec![l0, l1]
+    }
+
+    #[test]
+    fn counts_a_level() {
+        let stats = levels().iter().map(LevelStats::new).collect::<Vec<_>>();
+
+        let l0 = &stats[0];
+        assert_eq!((l0.vertices, l0.walls, l0.zones, l0.objects, l0.triggers), (3, 3, 1, 0, Some(1)));
+        assert_eq!(l0.bounds, Some(((-8, -32), (64, 0))));
+        assert_eq!(l0.textures.iter().cloned().collect::<Vec<_>>(), [0, 1, 7]);
+
+        let l1 = &stats[1];
+        assert_eq!((l1.vertices, l1.walls, l1.triggers, l1.bounds), (0, 0, None, None));
+        assert_eq!(l1.textures.iter().cloned().collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn finds_users_and_orphans() {
+        let stats = levels().iter().map(LevelStats::new).collect::<Vec<_>>();
+        let usage = Usage::new(2, &textures(), &stats);
+
+        assert_eq!(usage.texture_levels, [vec![0], vec![0, 1], vec![]]);
+        assert_eq!(usage.bitmap_textures, [vec![0, 1], vec![]]);
+        assert_eq!(usage.bitmap_levels(0), [0, 1]);
+        assert!(usage.bitmap_levels(1).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use std::io;
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use gob::Level;
+
+/// Bytes in one trigger record.
+const TRIGGER_SIZE: usize = 14;
+
+/// What sets a trigger off: using a wall, entering a zone or picking up an object.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TriggerSource {
+    Wall(u16),
+    Zone(u16),
+    Object(u16)
+}
+
+/// What a trigger does to its target zone. A door moves the ceiling and a lift the floor,
+/// to `height` and back after `wait`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Door,
+    Lift,
+    Other(u16)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    pub source: TriggerSource,
+    pub target_zone: u16,
+    pub action: Action,
+    pub height: i16,
+    pub speed: u16,
+    pub wait: u16
+}
+
+/// Says that a level's extra bytes aren't a trigger list, for the warnings wherever that
+/// matters; each adds what it means there.
+pub fn not_a_trigger_list(level: &Level) -> String {
+    format!("its {} bytes after the objects aren't a trigger list as assumed", level.extra.len())
+}
+
+impl Level {
+    /// Doors, lifts and switches, decoded from the bytes after the level's objects. Nothing
+    /// in the GOB documents how VEdit stores them, so this assumes a u16 count followed by
+    /// that many records of:
+    ///
+    /// ```text
+    /// u16 source_kind    0 wall, 1 zone, 2 object
+    /// u16 source         index of the wall, zone or object
+    /// u16 target_zone
+    /// u16 action         0 door, 1 lift, anything else kept as is
+    /// i16 height         where the ceiling or floor moves to
+    /// u16 speed
+    /// u16 wait
+    /// ```
+    ///
+    /// Extra bytes that don't fit that exactly, or name a source kind other than those,
+    /// aren't taken to be triggers at all: this gives `None` and they stay as they were in
+    /// `extra`. A level with no extra bytes has no triggers.
+    pub fn triggers(&self) -> Option<Vec<Trigger>> {
+        if self.extra.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let mut buf = Cursor::new(&self.extra[..]);
+        let count = buf.read_u16::<LittleEndian>().ok()? as usize;
+        if self.extra.len() != 2 + count * TRIGGER_SIZE {
+            return None;
+        }
+
+        let mut triggers = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (kind, source) = (buf.read_u16::<LittleEndian>().ok()?, buf.read_u16::<LittleEndian>().ok()?);
+            let source = match kind {
+                0 => TriggerSource::Wall(source),
+                1 => TriggerSource::Zone(source),
+                2 => TriggerSource::Object(source),
+                _ => return None
+            };
+            let target_zone = buf.read_u16::<LittleEndian>().ok()?;
+            let action = match buf.read_u16::<LittleEndian>().ok()? {
+                0 => Action::Door,
+                1 => Action::Lift,
+                a => Action::Other(a)
+            };
+
+            triggers.push(Trigger {
+                source, target_zone, action,
+                height: buf.read_i16::<LittleEndian>().ok()?,
+                speed: buf.read_u16::<LittleEndian>().ok()?,
+                wait: buf.read_u16::<LittleEndian>().ok()?
+            });
+        }
+
+        Some(triggers)
+    }
+
+    /// Replaces the level's triggers, writing them to `extra` in the layout `triggers`
+    /// reads. Fails if its extra bytes hold something else, which would otherwise be lost.
+    pub fn set_triggers(&mut self, triggers: &[Trigger]) -> Result<(), io::Error> {
+        if self.triggers().is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the level's extra bytes aren't a trigger list"));
+        }
+        if triggers.is_empty() && self.extra.is_empty() {
+            return Ok(());
+        }
+
+        let mut extra = Vec::with_capacity(2 + triggers.len() * TRIGGER_SIZE);
+        extra.write_u16::<LittleEndian>(triggers.len() as u16)?;
+        for t in triggers.iter() {
+            let (kind, source) = match t.source {
+                TriggerSource::Wall(w) => (0, w),
+                TriggerSource::Zone(z) => (1, z),
+                TriggerSource::Object(o) => (2, o)
+            };
+            let action = match t.action {
+                Action::Door => 0,
+                Action::Lift => 1,
+                Action::Other(a) => a
+            };
+
+            for &f in [kind, source, t.target_zone, action].iter() {
+                extra.write_u16::<LittleEndian>(f)?;
+            }
+            extra.write_i16::<LittleEndian>(t.height)?;
+            extra.write_u16::<LittleEndian>(t.speed)?;
+            extra.write_u16::<LittleEndian>(t.wait)?;
+        }
+        self.extra = extra;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gob::level::Level;
+    use super::{Action, Trigger, TriggerSource};
+
+    fn level(extra: Vec<u8>) -> Level {
+        Level { name: String::from("E1L1"), rebuild_zone: 0, vertices: Vec::new(), walls: Vec::new(), zones: Vec::new(),
+                objects: Vec::new(), header_extra: Vec::new(), extra }
+    }
+
+    #[test]
+    fn triggers_round_trip_through_extra_bytes() {
+        let triggers = [
+            Trigger { source: TriggerSource::Wall(3), target_zone: 1, action: Action::Door, height: 96, speed: 4, wait: 70 },
+            Trigger { source: TriggerSource::Object(0), target_zone: 2, action: Action::Other(9), height: -16, speed: 2, wait: 0 }
+        ];
+
+        let mut l = level(Vec::new());
+        assert_eq!(l.triggers(), Some(Vec::new()));
+        l.set_triggers(&triggers).unwrap();
+        assert_eq!(l.extra.len(), 2 + 2 * 14);
+        assert_eq!(&l.extra[..6], &[2, 0, 0, 0, 3, 0]);
+        assert_eq!(l.triggers().unwrap(), triggers);
+
+        l.set_triggers(&[]).unwrap();
+        assert_eq!(l.extra, [0, 0]);
+    }
+
+    #[test]
+    fn extra_bytes_that_are_not_triggers_are_kept() {
+        // One trigger's worth of bytes short, and an unknown source kind
+        let mut short = level(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut kind = level(vec![1, 0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for l in [&mut short, &mut kind].iter_mut() {
+            let extra = l.extra.clone();
+            assert_eq!(l.triggers(), None);
+            assert!(l.set_triggers(&[]).is_err());
+            assert_eq!(l.extra, extra);
+        }
+    }
+}
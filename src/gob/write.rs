@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use gob::TAG_HEADER_SIZE;
+use gob::map::Gob;
+
+pub fn write_header<W: Write>(buf: &mut W, id: u16, payload_size: usize) -> Result<(), io::Error> {
+    buf.write_u16::<LittleEndian>(id)?;
+    buf.write_u32::<LittleEndian>(payload_size as u32 + TAG_HEADER_SIZE)?;
+
+    Ok(())
+}
+
+pub fn write_tag<W: Write>(buf: &mut W, id: u16, payload: &[u8]) -> Result<(), io::Error> {
+    write_header(buf, id, payload.len())?;
+    buf.write_all(payload)
+}
+
+/// Writes `gob` back out tag for tag, swapping in new payloads for the tags in `replace`
+/// (keyed by position in `gob.index.tags`).
+pub fn rewrite<W: Write>(gob: &Gob, buf: &mut W, replace: &HashMap<usize, Vec<u8>>) -> Result<(), io::Error> {
+    for (i, entry) in gob.index.tags.iter().enumerate() {
+        match replace.get(&i) {
+            Some(payload) => write_tag(buf, entry.id, payload)?,
+            None => write_tag(buf, entry.id, gob.tag_data(entry)?)?
+        }
+    }
+
+    Ok(())
+}
@@ -1,219 +1,815 @@
 #![crate_name = "cabal_extract"]
-#![feature(step_by)]
 
 extern crate byteorder;
 extern crate bmp;
-
+extern crate memmap2;
+extern crate png;
+extern crate gif;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate ron;
+extern crate toml;
+
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::string::String;
-
-use std::io::Cursor;
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 
 use bmp::{Pixel, Image};
 
-mod postprocess;
-use postprocess::superxbr;
-
-struct Texture {
-    name: String,
-    bitmap_idx: usize,
-    colour_idx: usize,
-    pixel_size: f32
-}
+mod export;
+use export::atlas::{AtlasImage, AtlasOptions, UvFormat};
+use export::quake::TextureOutput;
+use export::render::{Camera, Renderer};
+mod gob;
+use gob::{clean_name, Bitmap, Palette, PaletteDepth};
+use gob::{link, music, write};
+use gob::catalogue::{Counts, ObjectTypes};
+use gob::edit::LevelEdit;
+use gob::shade::ShadeTable;
+use gob::stats::{LevelStats, Usage};
+use gob::sound::{self, RawFormat};
+use gob::map::Gob;
 
-struct Bitmap {
-    name: String,
-    width: u32,
-    height: u32,
-    data: Vec<u8>
+mod postprocess;
+use postprocess::convert;
+use postprocess::orient::{self, Orientation};
+
+mod project;
+
+/// Light levels rendered by `--shade` when the GOB's own tables aren't used.
+const DEFAULT_SHADE_LEVELS: usize = 16;
+
+/// What to do with the GOB. With no command, everything asked for is extracted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Extract,
+    Stats,
+    Diff,
+    Merge,
+    Unpack,
+    Pack,
+    Edit,
+    Lint
 }
 
-struct Palette {
-    r: [u8; 256],
-    g: [u8; 256],
-    b: [u8; 256],
+impl Command {
+    fn parse(s: &str) -> Option<Command> {
+        match s {
+            "stats" => Some(Command::Stats),
+            "diff" => Some(Command::Diff),
+            "merge" => Some(Command::Merge),
+            "unpack" => Some(Command::Unpack),
+            "pack" => Some(Command::Pack),
+            "edit" => Some(Command::Edit),
+            "lint" => Some(Command::Lint),
+            _ => None
+        }
+    }
 }
 
-struct Level {
-    rebuild_zone: u16
+struct Options {
+    command: Command,
+    path: String,
+    other_paths: Vec<String>,
+    out_path: Option<String>,
+    json: bool,
+    level: usize,
+    edits: Vec<LevelEdit>,
+    edit_errors: Vec<String>,
+    strict: bool,
+    orient: Orientation,
+    palettes: bool,
+    palette_depth: Option<PaletteDepth>,
+    shade: Option<usize>,
+    shade_tag: Option<u16>,
+    sprites: bool,
+    sprite_gifs: bool,
+    sprite_flags: u16,
+    atlas: Option<AtlasOptions>,
+    atlas_scale: bool,
+    svg: bool,
+    objects: bool,
+    object_types: Option<String>,
+    render: bool,
+    doom: bool,
+    quake: Option<TextureOutput>,
+    render_size: (u32, u32),
+    camera: Option<(f32, f32, f32)>,
+    music: bool,
+    sounds: bool,
+    sound_tag: Option<u16>,
+    sound_raw: RawFormat,
+    import_sounds: Option<String>
 }
 
-fn read_string<T: ReadBytesExt>(buf: &mut T, len: usize) -> String {
-    let mut str = vec![' ' as u8; len];
-    for i in 0..len {
-        match buf.read_u8() {
-            Ok(b) => str[i] = b,
-            Err(err) => println!("error {}", err)
+fn parse_args() -> Options {
+    let mut opts = Options { command: Command::Extract, path: String::from("acabal.gob"), other_paths: Vec::new(),
+                             out_path: None, json: false, level: 0, edits: Vec::new(), edit_errors: Vec::new(), strict: false, orient: Orientation::Normal, palettes: false, palette_depth: None,
+                             shade: None, shade_tag: None, sprites: false, sprite_gifs: false, sprite_flags: 0,
+                             atlas: None, atlas_scale: false,
+                             svg: false, objects: false, object_types: None, render: false, doom: false, quake: None, render_size: (320, 200), camera: None, music: false, sounds: false, sound_tag: None, sound_raw: RawFormat::default(), import_sounds: None };
+    let mut atlas = AtlasOptions { max_size: 1024, padding: 2, extrude: 1, format: UvFormat::Json };
+    let mut use_atlas = false;
+    let mut positional = Vec::new();
+
+    for arg in std::env::args().skip(1) {
+        match &arg[..] {
+            "--strict" => opts.strict = true,
+            "--json" => opts.json = true,
+            _ if arg.starts_with("--level=") => match arg["--level=".len()..].parse() {
+                Ok(n) => opts.level = n,
+                Err(_) => {
+                    eprintln!("invalid level index {}", arg);
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with("--out=") => opts.out_path = Some(String::from(&arg["--out=".len()..])),
+            "--palettes" => opts.palettes = true,
+            "--atlas" => use_atlas = true,
+            "--atlas-scale" => {
+                use_atlas = true;
+                opts.atlas_scale = true
+            },
+            "--atlas-format=json" => atlas.format = UvFormat::Json,
+            "--atlas-format=ron" => atlas.format = UvFormat::Ron,
+            _ if arg.starts_with("--atlas-size=") => match arg["--atlas-size=".len()..].parse() {
+                Ok(n) if n > 0 => atlas.max_size = n,
+                _ => eprintln!("invalid atlas size {}", arg)
+            },
+            _ if arg.starts_with("--atlas-padding=") => match arg["--atlas-padding=".len()..].parse() {
+                Ok(n) => atlas.padding = n,
+                Err(_) => eprintln!("invalid atlas padding {}", arg)
+            },
+            _ if arg.starts_with("--atlas-extrude=") => match arg["--atlas-extrude=".len()..].parse() {
+                Ok(n) => atlas.extrude = n,
+                Err(_) => eprintln!("invalid atlas extrusion {}", arg)
+            },
+            "--svg" => opts.svg = true,
+            "--objects" => opts.objects = true,
+            _ if arg.starts_with("--object-types=") => {
+                opts.objects = true;
+                opts.object_types = Some(String::from(&arg["--object-types=".len()..]))
+            },
+            "--render" => opts.render = true,
+            "--doom" => opts.doom = true,
+            "--quake" => opts.quake = Some(opts.quake.unwrap_or(TextureOutput::Wad3)),
+            "--quake-textures=wad" => opts.quake = Some(TextureOutput::Wad3),
+            "--quake-textures=png" => opts.quake = Some(TextureOutput::Png),
+            _ if arg.starts_with("--render-size=") => match parse_size(&arg["--render-size=".len()..]) {
+                Some(size) => {
+                    opts.render = true;
+                    opts.render_size = size
+                },
+                None => eprintln!("invalid render size {}", arg)
+            },
+            _ if arg.starts_with("--camera=") => match parse_camera(&arg["--camera=".len()..]) {
+                Some(camera) => {
+                    opts.render = true;
+                    opts.camera = Some(camera)
+                },
+                None => eprintln!("invalid camera {}", arg)
+            },
+            "--music" => opts.music = true,
+            "--sounds" => opts.sounds = true,
+            _ if arg.starts_with("--sound-tag=") => match parse_u16(&arg["--sound-tag=".len()..]) {
+                Some(id) if sound::is_known_tag(id) => eprintln!("tag id {} holds palettes, bitmaps, textures or levels, not sounds", id),
+                Some(id) => opts.sound_tag = Some(id),
+                None => eprintln!("invalid tag id {}", arg)
+            },
+            _ if arg.starts_with("--sound-rate=") => match arg["--sound-rate=".len()..].parse() {
+                Ok(n) if n > 0 => opts.sound_raw.sample_rate = n,
+                _ => eprintln!("invalid sample rate {}", arg)
+            },
+            _ if arg.starts_with("--sound-bits=") => match arg["--sound-bits=".len()..].parse() {
+                Ok(n) if n == 8 || n == 16 => opts.sound_raw.bits = n,
+                _ => eprintln!("invalid sample size {}", arg)
+            },
+            "--sound-signed" => opts.sound_raw.signed = true,
+            "--sound-unsigned" => opts.sound_raw.signed = false,
+            _ if arg.starts_with("--import-sounds=") => opts.import_sounds = Some(String::from(&arg["--import-sounds=".len()..])),
+            "--sprites" => opts.sprites = true,
+            "--sprite-gifs" => {
+                opts.sprites = true;
+                opts.sprite_gifs = true
+            },
+            _ if arg.starts_with("--sprite-flags=") => match parse_u16(&arg["--sprite-flags=".len()..]) {
+                Some(mask) => opts.sprite_flags = mask,
+                None => eprintln!("invalid flag mask {}", arg)
+            },
+            "--shade" => opts.shade = Some(DEFAULT_SHADE_LEVELS),
+            _ if arg.starts_with("--shade=") => match arg["--shade=".len()..].parse() {
+                Ok(n) if n > 0 => opts.shade = Some(n),
+                _ => eprintln!("invalid light level count {}", arg)
+            },
+            _ if arg.starts_with("--shade-tag=") => match parse_u16(&arg["--shade-tag=".len()..]) {
+                Some(id) => opts.shade_tag = Some(id),
+                None => eprintln!("invalid tag id {}", arg)
+            },
+            _ if arg.starts_with("--palette-depth=") => match &arg["--palette-depth=".len()..] {
+                "auto" => opts.palette_depth = None,
+                d => match PaletteDepth::parse(d) {
+                    Some(d) => opts.palette_depth = Some(d),
+                    None => eprintln!("unknown palette depth {}", arg)
+                }
+            },
+            _ if arg.starts_with("--orient=") => match Orientation::parse(&arg["--orient=".len()..]) {
+                Some(o) => opts.orient = o,
+                None => eprintln!("unknown orientation {}", arg)
+            },
+            _ if arg.starts_with("--") => match LevelEdit::parse(&arg) {
+                Some(Ok(edit)) => opts.edits.push(edit),
+                Some(Err(e)) => opts.edit_errors.push(e),
+                None => eprintln!("unknown option {}", arg)
+            },
+            _ => positional.push(arg)
         }
     }
 
-    return String::from_utf8_lossy(str.as_slice()).into_owned();
+    if let Some(command) = positional.first().and_then(|p| Command::parse(p)) {
+        opts.command = command;
+        positional.remove(0);
+    }
+    let mut positional = positional.into_iter();
+    if let Some(path) = positional.next() {
+        opts.path = path;
+    }
+    if opts.command == Command::Diff || opts.command == Command::Merge {
+        opts.other_paths.extend(&mut positional);
+    }
+    for arg in positional {
+        eprintln!("unexpected argument {}", arg);
+    }
+
+    if use_atlas {
+        opts.atlas = Some(atlas);
+    }
+
+    opts
 }
 
-fn read_header<T: ReadBytesExt>(mut buf: &mut T) -> Result<(u16, u32), std::io::Error> {
-    let id = buf.read_u16::<LittleEndian>()? as u16;
-    let tag_size = buf.read_u32::<LittleEndian>()? as u32;
+/// Parses a decimal or `0x` prefixed hex number.
+fn parse_u16(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
 
-    Ok((id, tag_size))
+/// Parses `<width>x<height>`.
+fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, 'x');
+    match (parts.next().and_then(|w| w.parse().ok()), parts.next().and_then(|h| h.parse().ok())) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => Some((w, h)),
+        _ => None
+    }
 }
 
-fn read_file_info<T: ReadBytesExt>(mut buf: &mut T) {
-    println!("GOB Info:");
-    println!(" -- Name: {}", read_string(&mut buf, 32));
-    println!(" -- Author: {}", read_string(&mut buf, 32));
-    println!(" -- Date: {}", read_string(&mut buf, 32));
-    println!(" -- VEDIT Version: {}", read_string(&mut buf, 16));
+/// Parses `<x>,<y>[,<angle>]`, the angle in degrees.
+fn parse_camera(s: &str) -> Option<(f32, f32, f32)> {
+    let parts: Vec<f32> = match s.split(',').map(|p| p.trim().parse()).collect() {
+        Ok(parts) => parts,
+        Err(_) => return None
+    };
+
+    match parts.len() {
+        2 => Some((parts[0], parts[1], 0.0)),
+        3 => Some((parts[0], parts[1], parts[2])),
+        _ => None
+    }
 }
 
-fn read_palette_data<T: ReadBytesExt>(mut buf: &mut T) -> Palette {
-    let mut p = Palette { r: [0; 256], g: [0; 256], b: [0; 256] };
+fn main() {
+//    fs::create_dir("./out").unwrap();
 
-    for i in 0..256 {
-        p.r[i] = buf.read_u8().unwrap();
+    let opts = parse_args();
+    if opts.command == Command::Pack {
+        return pack_project(&opts);
     }
-    for i in 0..256 {
-        p.g[i] = buf.read_u8().unwrap();
+
+    let mut gob = Gob::open(&opts.path, opts.strict).unwrap();
+    gob.index.palette_depth = opts.palette_depth;
+
+    match opts.command {
+        Command::Stats => return print_stats(&gob),
+        Command::Diff => return diff_gobs(&gob, &opts),
+        Command::Merge => return merge_gobs(&gob, &opts),
+        Command::Unpack => return unpack_gob(&gob, &opts),
+        Command::Edit => return edit_level(&gob, &opts),
+        Command::Lint => return lint_gob(&gob, &opts),
+        Command::Pack | Command::Extract => {}
     }
-    for i in 0..256 {
-        p.b[i] = buf.read_u8().unwrap();
+
+    let index = &gob.index;
+    gob.print_file_info().unwrap();
+
+    println!("Found {} bitmaps", index.bitmap_count());
+    println!("Found {} palettes", index.palette_count());
+    println!("Found {} textures", index.texture_count());
+    println!("Found {} levels", index.level_count());
+
+    if opts.palettes {
+        let dir = Path::new("out/palettes");
+        fs::create_dir_all(dir).unwrap();
+        for i in 0..index.palette_count() {
+            println!("Saving palette {}...", i);
+            export::palette::export(&gob.palette(i).unwrap(), dir, &format!("palette_{}", i)).unwrap();
+        }
     }
 
-    return p
-}
+    if opts.svg {
+        let dir = Path::new("out/levels");
+        fs::create_dir_all(dir).unwrap();
 
-fn read_bitmap_data<T: ReadBytesExt>(mut buf: &mut T) -> Bitmap {
-    let hdr_size = buf.read_u16::<LittleEndian>().unwrap();
-    let name = read_string(&mut buf, 14);
-    let x_len = buf.read_u16::<LittleEndian>().unwrap() as u32;
-    let y_len = buf.read_u16::<LittleEndian>().unwrap() as u32;
-    let flags = buf.read_u16::<LittleEndian>().unwrap();
+        for i in 0..index.level_count() {
+            let level = gob.level(i).unwrap();
+            println!("Saving level map {} ({})...", i, clean_name(&level.name));
+            let mut file = BufWriter::new(File::create(dir.join(format!("level_{}.svg", i))).unwrap());
+            export::svg::write_level_svg(&level, &mut file).unwrap();
+        }
+    }
 
-    let sz: u32 = x_len as u32 * y_len as u32;
-    let mut data = Vec::new();
+    if opts.objects {
+        let dir = Path::new("out/objects");
+        fs::create_dir_all(dir).unwrap();
+
+        let mut types = ObjectTypes::built_in();
+        if let Some(ref path) = opts.object_types {
+            types.overlay(ObjectTypes::read(path).unwrap());
+        }
+        if types.object.is_empty() {
+            eprintln!("warning: no object kinds are known, so every object is counted as unknown; list them with --object-types");
+        }
+        for i in 0..index.level_count() {
+            let level = gob.level(i).unwrap();
+            let name = clean_name(&level.name);
+            let placements = gob::catalogue::catalogue(&level, &types);
+            let counts = Counts::new(&placements);
+
+            println!("Saving level objects {} ({}, {} objects)...", i, name, placements.len());
+            for (difficulty, categories) in counts.categories.iter() {
+                let list: Vec<String> = categories.iter().map(|(c, n)| format!("{} {}", n, c.name())).collect();
+                println!("  {}: {}", difficulty, list.join(", "));
+            }
+
+            export::objects::write_csv(&placements, &mut BufWriter::new(File::create(dir.join(format!("level_{}.csv", i))).unwrap())).unwrap();
+            let file = BufWriter::new(File::create(dir.join(format!("level_{}.json", i))).unwrap());
+            export::objects::write_json(i, name, &placements, &counts, file).unwrap();
+        }
+    }
 
-    for _ in 0..sz {
-        data.push(buf.read_u8().unwrap());
+    if opts.music {
+        let dir = Path::new("out/music");
+        fs::create_dir_all(dir).unwrap();
+
+        for (_, track) in music::find_music(&gob).unwrap() {
+            let (ext, data) = match export::midi::to_midi(&track).unwrap() {
+                Some(midi) => ("mid", midi),
+                None => (track.format.extension(), track.data.clone())
+            };
+            println!("Saving music {} ({:?} as .{})...", track.name, track.format, ext);
+            File::create(dir.join(format!("{}.{}", track.name, ext))).unwrap().write_all(&data).unwrap();
+        }
     }
 
-    let bmp = Bitmap { name: name, width: x_len, height: y_len, data: data };
+    if opts.sounds || opts.import_sounds.is_some() {
+        extract_sounds(&gob, &opts);
+    }
 
-    return bmp
+    let (textures, dangling) = link::link(&gob).unwrap();
+    for d in dangling.iter() {
+        eprintln!("warning: {}", d);
+    }
+    let fallback = link::fallback_palette(&gob).unwrap();
+
+    if opts.sprites {
+        let dir = Path::new("out/sprites");
+        fs::create_dir_all(dir).unwrap();
+
+        let sprites = export::sprites::find_sprites(&gob, &textures, opts.sprite_flags).unwrap();
+        for seq in export::sprites::group_sequences(&gob, &sprites).unwrap() {
+            println!("Saving sprite sheet {} ({} frames)...", seq.name, seq.frames.len());
+            export::sprites::write_sheet(&seq, &fallback, dir).unwrap();
+            if opts.sprite_gifs {
+                export::sprites::write_gif(&seq, &fallback, &dir.join(format!("{}.gif", seq.file))).unwrap();
+            }
+        }
+    }
+
+    let gob_tables = match opts.shade_tag {
+        Some(id) => gob.shade_tables(id).unwrap(),
+        None => Vec::new()
+    };
+
+    if opts.doom {
+        let dir = Path::new("out/doom");
+        fs::create_dir_all(dir).unwrap();
+
+        for i in 0..index.level_count() {
+            let level = gob.level(i).unwrap();
+            println!("Saving Doom map {} ({})...", i, clean_name(&level.name));
+            let lumps = export::udmf::level_pwad(&gob, &level, &textures, &fallback).unwrap();
+            let mut file = BufWriter::new(File::create(dir.join(format!("level_{}.wad", i))).unwrap());
+            export::udmf::write_pwad(&mut file, &lumps).unwrap();
+        }
+    }
+
+    if let Some(output) = opts.quake {
+        export_quake(&gob, &textures, &fallback, output);
+    }
+
+    if opts.render {
+        render_levels(&gob, &textures, &fallback, &gob_tables, &opts);
+    }
+
+    let mut atlas_images = Vec::new();
+
+    for (i, t) in textures.iter().enumerate() {
+        let bmp = match t.bitmap {
+            Some(h) => gob.bitmap(h.index()).unwrap(),
+            None => link::placeholder_bitmap(&t.texture.name)
+        };
+        let own_palette = t.palette.map(|h| gob.palette(h.index()).unwrap());
+        let palette = own_palette.as_ref().unwrap_or(&fallback);
+        let bmp = orient::orient(&bmp, opts.orient);
+
+        println!("Saving texture {}...", i);
+        save_texture(&bmp, palette, &format!("out/{}.bmp", i));
+
+        if opts.atlas.is_some() {
+            let pixels = convert::to_rgba(&bmp, palette);
+            let name = String::from(clean_name(&t.texture.name));
+            atlas_images.push(if opts.atlas_scale {
+                AtlasImage { name, width: bmp.width * 2, height: bmp.height * 2,
+                             pixels: convert::scale2x(&pixels, bmp.width, bmp.height) }
+            } else {
+                AtlasImage { name, width: bmp.width, height: bmp.height, pixels }
+            });
+        }
+
+        if let Some(shade_levels) = opts.shade {
+            let own_table;
+            let table = match gob_tables.get(t.texture.colour_idx) {
+                Some(table) => table,
+                None => {
+                    own_table = ShadeTable::build(palette, shade_levels);
+                    &own_table
+                }
+            };
+
+            for level in 0..table.level_count() {
+                save_texture(&table.apply(&bmp, level), palette, &format!("out/{}_light{}.bmp", i, level));
+            }
+        }
+    }
+
+    if let Some(ref atlas) = opts.atlas {
+        let dir = Path::new("out/atlas");
+        fs::create_dir_all(dir).unwrap();
+
+        let (atlases, uvs) = export::atlas::build(&atlas_images, atlas).unwrap();
+        println!("Saving {} textures on {} atlases...", uvs.len(), atlases.len());
+        export::atlas::write(&atlases, &uvs, atlas, dir).unwrap();
+    }
 }
 
-fn read_texture_data<T: ReadBytesExt>(mut buf: &mut T) -> Texture {
-    let mut texture = Texture { name: String::from(""), bitmap_idx: 0, colour_idx: 0, pixel_size: 0.0 };
-    texture.name = read_string(&mut buf, 32);
-    texture.bitmap_idx = buf.read_u16::<LittleEndian>().unwrap() as usize;
-    texture.colour_idx = buf.read_u16::<LittleEndian>().unwrap() as usize;
-    texture.pixel_size = buf.read_f32::<LittleEndian>().unwrap();
+/// Writes every sound out as a WAV and, with `--import-sounds`, writes a copy of the GOB
+/// with sounds replaced by same-named WAVs from that directory.
+fn extract_sounds(gob: &Gob, opts: &Options) {
+    let dir = Path::new("out/sounds");
+    fs::create_dir_all(dir).unwrap();
+
+    let ids = sound::sound_tag_ids(gob).unwrap();
+    if !ids.is_empty() {
+        println!("Sounds are in tags with id {}", ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", "));
+    }
+
+    let mut replace = HashMap::new();
+    for (tag, mut s) in sound::find_sounds(gob, opts.sound_tag, opts.sound_raw).unwrap() {
+        if opts.sounds {
+            println!("Saving sound {} ({} Hz, {} bit)...", s.name, s.sample_rate, s.bits);
+            let mut file = BufWriter::new(File::create(dir.join(format!("{}.wav", s.name))).unwrap());
+            sound::write_wav(&s, &mut file).unwrap();
+        }
+
+        if let Some(ref import) = opts.import_sounds {
+            let path = Path::new(import).join(format!("{}.wav", s.name));
+            if path.exists() {
+                println!("Importing {}...", path.display());
+                if let Err(e) = s.replace_from_wav(&mut BufReader::new(File::open(&path).unwrap())) {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+                replace.insert(tag, sound::encode_sound(&s).unwrap());
+            }
+        }
+    }
 
-    return texture
+    if opts.import_sounds.is_some() {
+        let mut file = BufWriter::new(File::create("out/acabal.gob").unwrap());
+        write::rewrite(gob, &mut file, &replace).unwrap();
+    }
 }
 
-fn read_level_data<T: ReadBytesExt>(mut buf: &mut T) -> Level {
-    let mut level = Level { rebuild_zone: 0 };
-//    let hdr_size = buf.read_u16::<LittleEndian>().unwrap();
+/// Prints each level's counts, extents and textures, then which levels use every texture
+/// and bitmap, flagging the ones nothing uses.
+fn print_stats(gob: &Gob) {
+    let (textures, dangling) = link::link(gob).unwrap();
+    for d in dangling.iter() {
+        eprintln!("warning: {}", d);
+    }
+
+    let mut levels = Vec::new();
+    for i in 0..gob.index.level_count() {
+        levels.push(LevelStats::new(&gob.level(i).unwrap()));
+    }
+    let texture_name = |t: usize| match textures.get(t) {
+        Some(linked) => String::from(clean_name(&linked.texture.name)),
+        None => String::from("(missing)")
+    };
+
+    for (i, l) in levels.iter().enumerate() {
+        println!("Level {} ({})", i, clean_name(&l.name));
+        println!("  {} vertices, {} walls, {} zones, {} objects", l.vertices, l.walls, l.zones, l.objects);
+        match l.triggers {
+            Some(n) => println!("  {} triggers", n),
+            None => println!("  triggers: extra bytes not understood")
+        }
+        match l.bounds {
+            Some(((x0, y0), (x1, y1))) => println!("  bounds ({}, {}) to ({}, {}), {} x {}",
+                                                   x0, y0, x1, y1, x1 as i32 - x0 as i32, y1 as i32 - y0 as i32),
+            None => println!("  bounds: none")
+        }
+        println!("  textures used:");
+        for &t in l.textures.iter() {
+            println!("    {} {}", t, texture_name(t));
+        }
+    }
 
-    return level
+    let usage = Usage::new(gob.index.bitmap_count(), &textures, &levels);
+    let list = |items: &[usize]| items.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+
+    println!("Textures");
+    for (t, users) in usage.texture_levels.iter().enumerate() {
+        if users.is_empty() {
+            println!("  {} {}: unused", t, texture_name(t));
+        } else {
+            println!("  {} {}: levels {}", t, texture_name(t), list(users));
+        }
+    }
+
+    println!("Bitmaps");
+    for (b, users) in usage.bitmap_textures.iter().enumerate() {
+        let name = gob.bitmap_header(b).map(|h| String::from(clean_name(&h.name))).unwrap_or_default();
+        if users.is_empty() {
+            println!("  {} {}: unused", b, name);
+        } else {
+            let levels = usage.bitmap_levels(b);
+            println!("  {} {}: textures {}; levels {}", b, name, list(users),
+                     if levels.is_empty() { String::from("none") } else { list(&levels) });
+        }
+    }
 }
 
+/// Compares the GOB against the one named after it, printing the differences as text or
+/// JSON and writing an image of every bitmap whose pixels changed to `out/diff/`.
+fn diff_gobs(old: &Gob, opts: &Options) {
+    let path = match opts.other_paths.first() {
+        Some(path) => path,
+        None => {
+            println!("diff needs two GOBs: cabal-extract diff <old.gob> <new.gob>");
+            return;
+        }
+    };
+    let mut new = Gob::open(path, opts.strict).unwrap();
+    new.index.palette_depth = opts.palette_depth;
+
+    let mut changes = gob::diff::diff(old, &new).unwrap();
+
+    let dir = Path::new("out/diff");
+    let (old_textures, new_textures) = (link::link(old).unwrap().0, link::link(&new).unwrap().0);
+    for item in changes.bitmaps.iter_mut().filter(|b| b.pixels_changed.is_some()) {
+        let (a, b) = (item.old_index.unwrap(), item.new_index.unwrap());
+        let old_pal = link::bitmap_palette(old, &old_textures, a).unwrap();
+        let new_pal = link::bitmap_palette(&new, &new_textures, b).unwrap();
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(format!("bitmap_{}.png", b));
+        export::image::write_pixel_diff(&old.bitmap(a).unwrap(), &new.bitmap(b).unwrap(), &old_pal, &new_pal, &path).unwrap();
+        item.image = Some(path.display().to_string());
+    }
 
-fn main() {
-//    fs::create_dir("./out").unwrap();
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else if changes.is_empty() {
+        println!("No differences");
+    } else {
+        print!("{}", changes);
+    }
+}
 
-    let mut file = File::open("acabal.gob").unwrap();
+/// Overlays the patch GOBs named after the base onto it and writes the result to `--out`.
+fn merge_gobs(base: &Gob, opts: &Options) {
+    if opts.other_paths.is_empty() {
+        println!("merge needs a base and at least one patch: cabal-extract merge <base.gob> <patch.gob>...");
+        return;
+    }
 
-    let mut data = Vec::new();
-    file.read_to_end(&mut data);
-    let mut buffer = Cursor::new(&data);
+    let mut patches = Vec::new();
+    for path in opts.other_paths.iter() {
+        let patch = Gob::open(path, opts.strict).unwrap();
+        patches.push(patch);
+    }
 
-    let mut palettes = Vec::new();
-    let mut bitmaps = Vec::new();
-    let mut textures = Vec::new();
-    let mut levels = Vec::new();
+    let merged = match gob::merge::merge(base, &patches) {
+        Ok(merged) => merged,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    for o in merged.overlays.iter() {
+        println!("{}", o);
+    }
+    for &(p, entry) in merged.ignored.iter() {
+        eprintln!("warning: left out tag {} at offset {} of {}: only palettes, bitmaps, textures and levels are merged",
+                  entry.id, entry.offset - gob::TAG_HEADER_SIZE as u64, opts.other_paths[p]);
+    }
 
-    loop {
-        let header = match read_header(&mut buffer){
-            Ok(x) => x,
-            Err(e) => break
-        };
+    let out = Path::new(opts.out_path.as_ref().map_or("out/acabal.gob", |p| &p[..]));
+    if let Some(dir) = out.parent() {
+        fs::create_dir_all(dir).unwrap();
+    }
+    println!("Saving {}...", out.display());
+    merged.write(&mut BufWriter::new(File::create(out).unwrap())).unwrap();
+}
+
+/// Checks every level, exiting with status 1 if anything was found.
+fn lint_gob(gob: &Gob, opts: &Options) {
+    #[derive(Serialize)]
+    struct LevelIssues {
+        level: usize,
+        name: String,
+        issues: Vec<gob::lint::Issue>
+    }
 
-        match header.0 {
-            2 => {
-                let p = read_palette_data(&mut buffer);
-                palettes.push(p);
-            },
-            14 => {
-                let b = read_bitmap_data(&mut buffer);
-                bitmaps.push(b);
-            },
-            15 => {
-                let t = read_texture_data(&mut buffer);
-                textures.push(t);
-            },
-            20 => read_file_info(&mut buffer),
-            33 => println!("old level data"),
-            40 => {
-                let _ = buffer.seek(SeekFrom::Current((header.1 as i64) - 6));
-                let l = read_level_data(&mut buffer);
-                levels.push(l)
-            },
-            _ => {
-                println!("unknown id {}", header.0);
-                let _ = buffer.seek(SeekFrom::Current((header.1 as i64) - 6));
+    let mut levels = Vec::new();
+    for i in 0..gob.index.level_count() {
+        let level = gob.level(i).unwrap();
+        let issues = gob::lint::lint(&level, gob.index.texture_count());
+        levels.push(LevelIssues { level: i, name: String::from(clean_name(&level.name)), issues });
+    }
+
+    let count: usize = levels.iter().map(|l| l.issues.len()).sum();
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&levels).unwrap());
+    } else {
+        for l in levels.iter().filter(|l| !l.issues.is_empty()) {
+            println!("Level {} ({})", l.level, l.name);
+            for issue in l.issues.iter() {
+                println!("  {}", issue);
             }
         }
+        println!("{} problems in {} levels", count, levels.len());
     }
 
-    println!("Found {} bitmaps", bitmaps.len());
-    println!("Found {} palettes", palettes.len());
-    println!("Found {} textures", textures.len());
-    println!("Found {} levels", levels.len());
+    if count > 0 {
+        std::process::exit(1);
+    }
+}
 
-    for (i, t) in textures.iter().enumerate() {
-        let bmp = &bitmaps[t.bitmap_idx];
-        let mut img = Image::new(bmp.width * 2, bmp.height * 2);
-//        let mut img = Image::new(bmp.width, bmp.height);
-
-        let mut to_scale = vec![0u32; (bmp.width * bmp.height) as usize];
-        let mut scaled = vec![0u32; (bmp.width * bmp.height * 4) as usize];
-
-        for y in 0..bmp.height {
-            for x in 0..bmp.width {
-                let y_flip: u32 = (y as i32 - (bmp.height - 1) as i32).abs() as u32;
-                let pixel = bmp.data[((y_flip * bmp.width) + x) as usize];
-                let r = palettes[t.colour_idx].r[pixel as usize];
-                let g = palettes[t.colour_idx].g[pixel as usize];
-                let b = palettes[t.colour_idx].b[pixel as usize];
-
-                let p: u32 = (r as u32) | ((g as u32) << 8) | ((b as u32) << 16) | ((255 as u32) << 24);
-                to_scale[((y * bmp.width) + x) as usize] = p;
-//                img.set_pixel(x, y, Pixel { r: r, g: g, b: b });
-            }
+/// Applies the edits to one level in order and writes the GOB with it swapped in. Nothing
+/// is written if any edit option couldn't be parsed.
+fn edit_level(gob: &Gob, opts: &Options) {
+    if !opts.edit_errors.is_empty() {
+        for e in opts.edit_errors.iter() {
+            eprintln!("invalid edit {}", e);
         }
+        std::process::exit(1);
+    }
 
-        superxbr::scale(to_scale.as_mut_slice(), scaled.as_mut_slice(), bmp.width as i32, bmp.height as i32);
+    let (textures, _) = link::link(gob).unwrap();
+    let mut level = gob.level(opts.level).unwrap();
+    for edit in opts.edits.iter() {
+        println!("Editing level {}: {}...", opts.level, edit);
+        edit.apply(&mut level, &textures).unwrap();
+    }
 
-//        for (x, y) in img.coordinates() {
-        for y in 0..bmp.height*2 {
-            for x in 0..bmp.width*2 {
-                let idx: usize = ((y * bmp.width*2) + x) as usize;
-                let r: u8 = (scaled[idx] & 0xff) as u8;
-                let g: u8 = ((scaled[idx] >> 8) & 0xff) as u8;
-                let b: u8 = ((scaled[idx] >> 16) & 0xff) as u8;
+    let mut levels = HashMap::new();
+    levels.insert(opts.level, level);
 
-                //            println!("r {} g {} b {}", r, g, b);
-                img.set_pixel(x, y, Pixel { r: r, g: g, b: b });
-            }
+    let out = Path::new(opts.out_path.as_ref().map_or("out/acabal.gob", |p| &p[..]));
+    if let Some(dir) = out.parent() {
+        fs::create_dir_all(dir).unwrap();
+    }
+    // Built in memory first so a rejected edit doesn't leave an empty file behind
+    let mut data = Vec::new();
+    gob::edit::rewrite_levels(gob, &mut data, &levels).unwrap();
+    println!("Saving {}...", out.display());
+    File::create(out).unwrap().write_all(&data).unwrap();
+}
+
+fn unpack_gob(gob: &Gob, opts: &Options) {
+    let dir = Path::new(opts.out_path.as_ref().map_or("out/project", |p| &p[..]));
+    println!("Unpacking {} to {}...", opts.path, dir.display());
+    if let Err(e) = project::unpack(gob, dir) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Here `opts.path` is an unpacked project directory rather than a GOB.
+fn pack_project(opts: &Options) {
+    let out = Path::new(opts.out_path.as_ref().map_or("out/acabal.gob", |p| &p[..]));
+    if let Some(dir) = out.parent() {
+        fs::create_dir_all(dir).unwrap();
+    }
+    println!("Packing {} to {}...", opts.path, out.display());
+    let mut file = BufWriter::new(File::create(out).unwrap());
+    project::pack(Path::new(&opts.path), &mut file).unwrap();
+    file.flush().unwrap();
+}
+
+/// Writes each level as a Valve 220 `.map`, with the textures as a WAD3 or as PNGs.
+fn export_quake(gob: &Gob, textures: &[link::LinkedTexture], palette: &Palette, output: TextureOutput) {
+    let dir = Path::new("out/quake");
+    fs::create_dir_all(dir).unwrap();
+
+    let map_textures = export::quake::map_textures(gob, textures, output).unwrap();
+    let wad = match output {
+        TextureOutput::Wad3 => {
+            println!("Saving {} textures to textures.wad...", map_textures.len());
+            let mut file = BufWriter::new(File::create(dir.join("textures.wad")).unwrap());
+            export::quake::write_wad3(&mut file, &map_textures, palette).unwrap();
+            Some("textures.wad")
+        },
+        TextureOutput::Png => {
+            println!("Saving {} textures to textures/...", map_textures.len());
+            let tex_dir = dir.join("textures");
+            fs::create_dir_all(&tex_dir).unwrap();
+            export::quake::write_pngs(&tex_dir, &map_textures, palette).unwrap();
+            None
         }
+    };
 
-        println!("Saving texture {}...", i);
-        match img.save(&format!("out/{}.bmp", i)[..]) {
-            Err(err) => panic!("{}", err),
-            Ok(x) => x
+    for i in 0..gob.index.level_count() {
+        let level = gob.level(i).unwrap();
+        println!("Saving Quake map {} ({})...", i, clean_name(&level.name));
+        let mut file = BufWriter::new(File::create(dir.join(format!("level_{}.map", i))).unwrap());
+        export::quake::write_map(&level, &map_textures, wad, &mut file).unwrap();
+    }
+}
+
+/// Renders each level from `--camera`, or from its rebuild zone, to an indexed PNG.
+fn render_levels(gob: &Gob, textures: &[link::LinkedTexture], palette: &Palette, gob_tables: &[ShadeTable], opts: &Options) {
+    let dir = Path::new("out/renders");
+    fs::create_dir_all(dir).unwrap();
+
+    let own_table;
+    let table = match gob_tables.first() {
+        Some(table) => table,
+        None => {
+            own_table = ShadeTable::build(palette, opts.shade.unwrap_or(DEFAULT_SHADE_LEVELS));
+            &own_table
+        }
+    };
+    let renderer = Renderer::new(gob, textures, table).unwrap();
+    let (width, height) = opts.render_size;
+
+    for i in 0..gob.index.level_count() {
+        let level = gob.level(i).unwrap();
+        let camera = match opts.camera {
+            Some((x, y, angle)) => Some(Camera::at(&level, x, y, angle)),
+            None => Camera::start(&level)
         };
+
+        match camera.and_then(|c| renderer.render(&level, &c, width, height)) {
+            Some(frame) => {
+                println!("Saving level render {} ({})...", i, clean_name(&level.name));
+                let file = BufWriter::new(File::create(dir.join(format!("level_{}.png", i))).unwrap());
+                export::image::write_indexed_png(file, width, height, palette, &frame.top_down(), None).unwrap();
+            },
+            None => eprintln!("warning: no camera position inside level {} ({})", i, clean_name(&level.name))
+        }
     }
 }
+
+/// Scales a texture 2x with Super-xBR and writes it as a BMP.
+fn save_texture(bmp: &Bitmap, palette: &Palette, path: &str) {
+    let mut img = Image::new(bmp.width * 2, bmp.height * 2);
+    let scaled = convert::scale2x(&convert::to_rgba(bmp, palette), bmp.width, bmp.height);
+
+    for y in 0..bmp.height*2 {
+        for x in 0..bmp.width*2 {
+            let (r, g, b, _) = convert::unpack(scaled[((y * bmp.width*2) + x) as usize]);
+            img.set_pixel(x, y, Pixel { r, g, b });
+        }
+    }
+
+    match img.save(path) {
+        Err(err) => panic!("{}", err),
+        Ok(x) => x
+    };
+}
@@ -0,0 +1,23 @@
+use gob::{Bitmap, Palette};
+use postprocess::superxbr;
+
+/// Looks every pixel up in the palette, giving packed `0xAABBGGRR` values as used by
+/// the Super-xBR scaler. Alpha is always opaque.
+pub fn to_rgba(bmp: &Bitmap, palette: &Palette) -> Vec<u32> {
+    bmp.top_down().iter().map(|&p| {
+        let (r, g, b) = (palette.r[p as usize], palette.g[p as usize], palette.b[p as usize]);
+        (r as u32) | ((g as u32) << 8) | ((b as u32) << 16) | (255 << 24)
+    }).collect()
+}
+
+/// Scales packed pixels 2x with Super-xBR.
+pub fn scale2x(pixels: &[u32], width: u32, height: u32) -> Vec<u32> {
+    let mut scaled = vec![0u32; (width * height * 4) as usize];
+    superxbr::scale(pixels, scaled.as_mut_slice(), width as i32, height as i32);
+
+    scaled
+}
+
+pub fn unpack(p: u32) -> (u8, u8, u8, u8) {
+    ((p & 0xff) as u8, ((p >> 8) & 0xff) as u8, ((p >> 16) & 0xff) as u8, ((p >> 24) & 0xff) as u8)
+}
@@ -1,2 +1,3 @@
 pub mod superxbr;
-pub mod convert;
\ No newline at end of file
+pub mod convert;
+pub mod orient;
\ No newline at end of file
@@ -0,0 +1,54 @@
+use gob::{Bitmap, RowOrder};
+
+/// How to reorient a bitmap before it is written out. VEdit stores some wall bitmaps
+/// column-major, which come out of the decoder transposed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    Transpose,
+    RotateCw,
+    RotateCcw,
+    FlipH,
+    FlipV
+}
+
+impl Orientation {
+    pub fn parse(s: &str) -> Option<Orientation> {
+        match s {
+            "normal" => Some(Orientation::Normal),
+            "transpose" => Some(Orientation::Transpose),
+            "cw" | "rotate-cw" => Some(Orientation::RotateCw),
+            "ccw" | "rotate-ccw" => Some(Orientation::RotateCcw),
+            "flip-h" => Some(Orientation::FlipH),
+            "flip-v" => Some(Orientation::FlipV),
+            _ => None
+        }
+    }
+}
+
+/// Returns a copy of `bmp` reoriented as requested. Input and output are both top-down,
+/// row-major.
+pub fn orient(bmp: &Bitmap, o: Orientation) -> Bitmap<'static> {
+    let (w, h) = (bmp.width, bmp.height);
+    let (out_w, out_h) = match o {
+        Orientation::Transpose | Orientation::RotateCw | Orientation::RotateCcw => (h, w),
+        _ => (w, h)
+    };
+
+    let mut data = vec![0u8; (w * h) as usize];
+    for y in 0..out_h {
+        for x in 0..out_w {
+            let (sx, sy) = match o {
+                Orientation::Normal => (x, y),
+                Orientation::Transpose => (y, x),
+                Orientation::RotateCw => (y, h - 1 - x),
+                Orientation::RotateCcw => (w - 1 - y, x),
+                Orientation::FlipH => (w - 1 - x, y),
+                Orientation::FlipV => (x, h - 1 - y)
+            };
+            data[(y * out_w + x) as usize] = bmp.pixel(sx, sy);
+        }
+    }
+
+    Bitmap::new(bmp.name.clone(), out_w, out_h, bmp.flags, RowOrder::TopDown, data)
+}
@@ -29,6 +29,9 @@ THE SOFTWARE.
 
 */
 
+// Kept as close to the original as possible, names and all
+#![allow(non_snake_case, unused_variables, unused_assignments, unused_mut, unused_parens)]
+
 macro_rules! red {
     ($x:expr) => (($x >> 0) & 0xff)
 }
@@ -50,11 +53,11 @@ fn df(a: f32, b: f32) -> f32 {
 }
 
 pub fn minv(v: Vec<f32>) -> f32 {
-    v.iter().cloned().fold(0. / 0., f32::min)
+    v.iter().cloned().fold(f32::NAN, f32::min)
 }
 
 pub fn maxv(v: Vec<f32>) -> f32 {
-    v.iter().cloned().fold(0. / 0., f32::max)
+    v.iter().cloned().fold(f32::NAN, f32::max)
 }
 
 pub fn clamp(v: i32, floor: i32, ceil: i32) -> i32 {
@@ -68,7 +71,7 @@ pub fn clamp(v: i32, floor: i32, ceil: i32) -> i32 {
         out = ceil;
     }
 
-    return out;
+    out
 }
 
 pub fn clampf(v: f32, floor: f32, ceil: f32) -> f32 {
@@ -82,7 +85,7 @@ pub fn clampf(v: f32, floor: f32, ceil: f32) -> f32 {
         out = ceil;
     }
 
-    return out;
+    out
 }
 
 pub fn diagonal_edge(mat: [[f32; 4]; 4], wp: [f32; 6]) -> f32 {
@@ -134,8 +137,8 @@ pub fn scale(data: &[u32], out: &mut [u32], width: i32, height: i32) {
             let y_rng: Vec<i32> = vec![-1, 0, 1, 2];
             for sx in x_rng.iter() {
                 for sy in y_rng.iter() {
-                    let csy = clamp(sy + cy as i32, 0, height - 1);
-                    let csx = clamp(sx + cx as i32, 0, width - 1);
+                    let csy = clamp(sy + cy, 0, height - 1);
+                    let csx = clamp(sx + cx, 0, width - 1);
                     let sample = data[(csy * width + csx) as usize];
 
                     r[(*sx + 1) as usize][(*sy + 1) as usize] = red!(sample) as f32;
@@ -219,8 +222,8 @@ pub fn scale(data: &[u32], out: &mut [u32], width: i32, height: i32) {
             let y_rng: Vec<i32> = vec![-1, 0, 1, 2];
             for sx in x_rng.iter() {
                 for sy in y_rng.iter() {
-                    let csy = clamp(sx - sy + y as i32, 0, f*height - 1);
-                    let csx = clamp(sx + sy + x as i32, 0, f*width - 1);
+                    let csy = clamp(sx - sy + y, 0, f*height - 1);
+                    let csx = clamp(sx + sy + x, 0, f*width - 1);
                     let sample = out[(csy * outw + csx) as usize];
 
                     r[(*sx + 1) as usize][(*sy + 1) as usize] = red!(sample) as f32;
@@ -282,8 +285,8 @@ pub fn scale(data: &[u32], out: &mut [u32], width: i32, height: i32) {
 
             for sx in x_rng.iter() {
                 for sy in y_rng.iter() {
-                    let csy = clamp(sx - sy + 1 + y as i32, 0, f*height - 1);
-                    let csx = clamp(sx + sy - 1 + x as i32, 0, f*width - 1);
+                    let csy = clamp(sx - sy + 1 + y, 0, f*height - 1);
+                    let csx = clamp(sx + sy - 1 + x, 0, f*width - 1);
                     let sample = out[(csy * outw + csx) as usize];
 
                     r[(*sx + 1) as usize][(*sy + 1) as usize] = red!(sample) as f32;
@@ -354,8 +357,8 @@ pub fn scale(data: &[u32], out: &mut [u32], width: i32, height: i32) {
             let y_rng: Vec<i32> = vec![-2, -1, 0, 1];
             for sx in x_rng.iter() {
                 for sy in y_rng.iter() {
-                    let csy = clamp(sy + y as i32, 0, f * height - 1);
-                    let csx = clamp(sx + x as i32, 0, f * width - 1);
+                    let csy = clamp(sy + y, 0, f * height - 1);
+                    let csx = clamp(sx + x, 0, f * width - 1);
                     let sample = out[(csy * outw + csx) as usize];
 
                     r[(*sx + 2) as usize][(*sy + 2) as usize] = red!(sample) as f32;
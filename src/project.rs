@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::Path;
+
+use toml;
+
+use export::image::{read_indexed_png, write_indexed_png};
+use export::palette::{read_jasc, write_jasc};
+use gob::{clean_name, file_stem, read_string, write_string, Bitmap, PaletteDepth, RowOrder, Texture};
+use gob::{read_level_data, read_level_text, write_bitmap_data, write_level_data, write_level_text, write_palette_data, write_texture_data};
+use gob::{TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_FILE_INFO, TAG_LEVEL};
+use gob::link;
+use gob::map::Gob;
+use gob::write::write_tag;
+
+const MANIFEST: &str = "info.toml";
+const TEXTURES: &str = "textures.toml";
+const TRAILING: &str = "trailing.bin";
+
+/// Contents of `info.toml`.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trailing: Option<String>,
+    tag: Vec<Entry>
+}
+
+/// One tag, in file order. A texture entry stands for the next texture in `textures.toml`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Entry {
+    Info { name: String, author: String, date: String, version: String },
+    Palette { file: String, #[serde(with = "depth_bits")] depth: PaletteDepth },
+    Bitmap { file: String, name: String, flags: u16, header_size: u16 },
+    Texture,
+    Level { file: String },
+    Raw { id: u16, file: String }
+}
+
+/// Contents of `textures.toml`.
+#[derive(Serialize, Deserialize)]
+struct TextureList {
+    #[serde(default)]
+    texture: Vec<TextureEntry>
+}
+
+/// Palette depths as bits per component, 6 or 8, in `info.toml`.
+mod depth_bits {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    use gob::PaletteDepth;
+
+    pub fn serialize<S: Serializer>(depth: &PaletteDepth, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(match *depth { PaletteDepth::Vga6 => 6, PaletteDepth::Rgb8 => 8 })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PaletteDepth, D::Error> {
+        match u8::deserialize(d)? {
+            6 => Ok(PaletteDepth::Vga6),
+            8 => Ok(PaletteDepth::Rgb8),
+            n => Err(D::Error::custom(format!("palette depth {} isn't 6 or 8", n)))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TextureEntry {
+    name: String,
+    bitmap: usize,
+    palette: usize,
+    pixel_size: f32
+}
+
+fn invalid<E: ToString>(file: &str, e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file, e.to_string()))
+}
+
+/// The name itself if writing it back gives the same field, otherwise the whole field,
+/// padding and all.
+fn field_name(field: &str, len: usize) -> String {
+    let (mut a, mut b) = (Vec::new(), Vec::new());
+    match (write_string(&mut a, clean_name(field), len), write_string(&mut b, field, len)) {
+        (Ok(_), Ok(_)) if a == b => String::from(clean_name(field)),
+        _ => String::from(field)
+    }
+}
+
+fn read_info(data: &[u8]) -> Result<Entry, io::Error> {
+    let mut c = Cursor::new(data);
+    Ok(Entry::Info {
+        name: field_name(&read_string(&mut c, 32)?, 32), author: field_name(&read_string(&mut c, 32)?, 32),
+        date: field_name(&read_string(&mut c, 32)?, 32), version: field_name(&read_string(&mut c, 16)?, 16)
+    })
+}
+
+/// Builds a tag's payload from its entry, reading any files it names with `read`.
+fn encode<F>(entry: &Entry, texture: Option<&TextureEntry>, read: F) -> Result<(u16, Vec<u8>), io::Error>
+    where F: Fn(&str) -> Result<Vec<u8>, io::Error>
+{
+    let mut payload = Vec::new();
+    let id = match *entry {
+        Entry::Info { ref name, ref author, ref date, ref version } => {
+            write_string(&mut payload, name, 32)?;
+            write_string(&mut payload, author, 32)?;
+            write_string(&mut payload, date, 32)?;
+            write_string(&mut payload, version, 16)?;
+            TAG_FILE_INFO
+        },
+        Entry::Palette { ref file, depth } => {
+            let p = read_jasc(Cursor::new(read(file)?), depth).map_err(|e| invalid(file, e))?;
+            write_palette_data(&p, &mut payload)?;
+            TAG_PALETTE
+        },
+        Entry::Bitmap { ref file, ref name, flags, header_size } => {
+            let (width, height, data) = read_indexed_png(Cursor::new(read(file)?)).map_err(|e| invalid(file, e))?;
+            if width > 0xffff || height > 0xffff {
+                return Err(invalid(file, format!("{}x{} is too big for a bitmap", width, height)));
+            }
+            let b = Bitmap::new(name.clone(), width, height, flags, RowOrder::TopDown, data);
+            write_bitmap_data(&b, header_size, &mut payload)?;
+            TAG_BITMAP
+        },
+        Entry::Texture => {
+            let t = texture.ok_or_else(|| invalid(TEXTURES, "fewer textures than info.toml lists"))?;
+            let t = Texture { name: t.name.clone(), bitmap_idx: t.bitmap, colour_idx: t.palette, pixel_size: t.pixel_size };
+            write_texture_data(&t, &mut payload)?;
+            TAG_TEXTURE
+        },
+        Entry::Level { ref file } => {
+            let text = String::from_utf8(read(file)?).map_err(|e| invalid(file, e))?;
+            let level = read_level_text(&text).map_err(|e| invalid(file, e))?;
+            write_level_data(&level, &mut payload)?;
+            TAG_LEVEL
+        },
+        Entry::Raw { id, ref file } => {
+            payload = read(file)?;
+            id
+        }
+    };
+
+    Ok((id, payload))
+}
+
+/// Unpacks every tag of `gob` into `dir`:
+///
+/// ```text
+/// info.toml       every tag in file order, and what it was unpacked to
+/// textures.toml   the texture tags, in order
+/// palettes/       palette_N.pal, JASC palettes
+/// bitmaps/        NNNN_NAME.png, indexed PNGs
+/// levels/         level_N.ron
+/// raw/            NNNN_ID.bin, payloads of tags kept as they are
+/// trailing.bin    bytes after the last tag, if there were any
+/// ```
+///
+/// A tag is only unpacked to its editable form if packing that form gives back the same
+/// bytes; anything else, such as a tag with bytes after the part the decoder reads, goes
+/// to `raw/`. Packing an untouched project therefore rebuilds the GOB byte for byte.
+///
+/// `dir` must be empty or not exist yet, so nothing left from another project gets packed
+/// along with this one.
+pub fn unpack(gob: &Gob, dir: &Path) -> Result<(), io::Error> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} isn't empty", dir.display())));
+    }
+
+    let (linked, _) = link::link(gob)?;
+    let fallback = link::fallback_palette(gob)?;
+
+    let mut manifest = Manifest { trailing: None, tag: Vec::new() };
+    let mut textures = TextureList { texture: Vec::new() };
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    let (mut palette_idx, mut bitmap_idx, mut texture_idx, mut level_idx) = (0, 0, 0, 0);
+
+    for (i, entry) in gob.index.tags.iter().enumerate() {
+        let data = gob.tag_data(entry)?;
+        let mut texture = None;
+        let mut unpacked: HashMap<String, Vec<u8>> = HashMap::new();
+
+        // Anything that fails to decode is left for the check below to send to raw/.
+        let candidate = match entry.id {
+            TAG_FILE_INFO if data.len() == 112 => read_info(data).ok(),
+            TAG_PALETTE => gob.palette(palette_idx).ok().and_then(|p| {
+                let file = format!("palettes/palette_{}.pal", palette_idx);
+                let mut text = Vec::new();
+                write_jasc(&p, &mut text).ok()?;
+                unpacked.insert(file.clone(), text);
+                Some(Entry::Palette { file, depth: p.depth })
+            }),
+            TAG_BITMAP => gob.bitmap(bitmap_idx).ok().and_then(|b| {
+                let hdr = gob.bitmap_header(bitmap_idx).ok()?;
+                let name = field_name(&b.name, 14);
+                let file = format!("bitmaps/{:04}_{}.png", bitmap_idx, file_stem(clean_name(&b.name)));
+
+                // The PNG's palette is only for viewing; the first texture using the bitmap
+                // says which one that should be.
+                let palette = linked.iter()
+                    .find(|t| t.bitmap.map(|h| h.index()) == Some(bitmap_idx))
+                    .and_then(|t| t.palette)
+                    .and_then(|h| gob.palette(h.index()).ok());
+                let mut png = Vec::new();
+                write_indexed_png(&mut png, b.width, b.height, palette.as_ref().unwrap_or(&fallback), &b.top_down(), None).ok()?;
+                unpacked.insert(file.clone(), png);
+                Some(Entry::Bitmap { file, name, flags: b.flags, header_size: hdr.hdr_size })
+            }),
+            TAG_TEXTURE => gob.texture(texture_idx).ok().and_then(|t| {
+                // Through TOML and back, as pack will see it.
+                let list = TextureList { texture: vec![TextureEntry {
+                    name: field_name(&t.name, 32), bitmap: t.bitmap_idx, palette: t.colour_idx, pixel_size: t.pixel_size
+                }] };
+                let text = toml::to_string(&list).ok()?;
+                texture = toml::from_str::<TextureList>(&text).ok()?.texture.pop();
+                Some(Entry::Texture)
+            }),
+            TAG_LEVEL => gob.level(level_idx).ok().and_then(|mut level| {
+                level.name = field_name(&level.name, 32);
+                let file = format!("levels/level_{}.ron", level_idx);
+                let mut text = Vec::new();
+                write_level_text(&level, &mut text).ok()?;
+                unpacked.insert(file.clone(), text);
+                Some(Entry::Level { file })
+            }),
+            _ => None
+        };
+        match entry.id {
+            TAG_PALETTE => palette_idx += 1,
+            TAG_BITMAP => bitmap_idx += 1,
+            TAG_TEXTURE => texture_idx += 1,
+            TAG_LEVEL => level_idx += 1,
+            _ => {}
+        }
+
+        let exact = candidate.as_ref().is_some_and(|c| {
+            let read = |f: &str| unpacked.get(f).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, f.to_string()));
+            match encode(c, texture.as_ref(), read) {
+                Ok((id, payload)) => id == entry.id && payload == data,
+                Err(_) => false
+            }
+        });
+
+        if exact {
+            manifest.tag.push(candidate.unwrap());
+            textures.texture.extend(texture);
+            files.extend(unpacked);
+        } else {
+            let file = format!("raw/{:04}_{}.bin", i, entry.id);
+            files.insert(file.clone(), data.to_vec());
+            manifest.tag.push(Entry::Raw { id: entry.id, file });
+        }
+    }
+
+    if !gob.trailing().is_empty() {
+        files.insert(String::from(TRAILING), gob.trailing().to_vec());
+        manifest.trailing = Some(String::from(TRAILING));
+    }
+
+    files.insert(String::from(MANIFEST), toml::to_string(&manifest).map_err(|e| invalid(MANIFEST, e))?.into_bytes());
+    files.insert(String::from(TEXTURES), toml::to_string(&textures).map_err(|e| invalid(TEXTURES, e))?.into_bytes());
+
+    for (name, contents) in files.iter() {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(path)?.write_all(contents)?;
+    }
+
+    Ok(())
+}
+
+/// Packs the project in `dir` back into a GOB.
+pub fn pack<W: Write>(dir: &Path, buf: &mut W) -> Result<(), io::Error> {
+    let read = |f: &str| {
+        let mut contents = Vec::new();
+        BufReader::new(File::open(dir.join(f))?).read_to_end(&mut contents)?;
+        Ok(contents)
+    };
+    let text = |f: &str| String::from_utf8(read(f)?).map_err(|e| invalid(f, e));
+
+    let manifest: Manifest = toml::from_str(&text(MANIFEST)?).map_err(|e| invalid(MANIFEST, e))?;
+    let textures: TextureList = if dir.join(TEXTURES).exists() {
+        toml::from_str(&text(TEXTURES)?).map_err(|e| invalid(TEXTURES, e))?
+    } else {
+        TextureList { texture: Vec::new() }
+    };
+
+    let texture_count = manifest.tag.iter().filter(|e| matches!(**e, Entry::Texture | Entry::Raw { id: TAG_TEXTURE, .. })).count();
+
+    let mut next_texture = textures.texture.iter();
+    for entry in manifest.tag.iter() {
+        let texture = match *entry {
+            Entry::Texture => next_texture.next(),
+            _ => None
+        };
+        let (id, payload) = encode(entry, texture, read)?;
+        if let Entry::Level { ref file } = *entry {
+            for d in read_level_data(&mut Cursor::new(&payload), payload.len() as u32)?.dangling(texture_count) {
+                eprintln!("warning: {}: {}", file, d);
+            }
+        }
+        write_tag(buf, id, &payload)?;
+    }
+    if next_texture.next().is_some() {
+        eprintln!("warning: {} lists more textures than info.toml has entries for", TEXTURES);
+    }
+
+    if let Some(ref file) = manifest.trailing {
+        buf.write_all(&read(file)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
+    use gob::{write_string, write_texture_data, Texture, TAG_PALETTE, TAG_BITMAP, TAG_TEXTURE, TAG_FILE_INFO, TAG_LEVEL};
+    use gob::level::{write_level_data, Level};
+    use gob::map::Gob;
+    use gob::write::write_tag;
+    use super::{pack, unpack};
+
+    /// A GOB with one of each kind of tag, a tag nothing understands and trailing bytes.
+    fn gob() -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let mut info = Vec::new();
+        for &(field, len) in [("TEST", 32), ("someone", 32), ("1994", 32), ("1.0", 16)].iter() {
+            write_string(&mut info, field, len).unwrap();
+        }
+        write_tag(&mut out, TAG_FILE_INFO, &info).unwrap();
+
+        let palette: Vec<u8> = (0..768).map(|i| (i % 64) as u8).collect();
+        write_tag(&mut out, TAG_PALETTE, &palette).unwrap();
+
+        let mut bitmap = vec![22, 0];
+        write_string(&mut bitmap, "WALL1", 14).unwrap();
+        bitmap.extend_from_slice(&[2, 0, 2, 0, 0, 0, 1, 2, 3, 4]);
+        write_tag(&mut out, TAG_BITMAP, &bitmap).unwrap();
+
+        let mut texture = Vec::new();
+        write_texture_data(&Texture { name: String::from("WALL1"), bitmap_idx: 0, colour_idx: 0, pixel_size: 1.0 }, &mut texture).unwrap();
+        write_tag(&mut out, TAG_TEXTURE, &texture).unwrap();
+
+        let level = Level { name: String::from("E1L1"), rebuild_zone: 0, vertices: Vec::new(), walls: Vec::new(), zones: Vec::new(),
+                            objects: Vec::new(), header_extra: Vec::new(), extra: Vec::new() };
+        let mut record = Vec::new();
+        write_level_data(&level, &mut record).unwrap();
+        write_tag(&mut out, TAG_LEVEL, &record).unwrap();
+
+        write_tag(&mut out, 99, &[1, 2, 3]).unwrap();
+        out.extend_from_slice(&[7, 7]);
+
+        out
+    }
+
+    #[test]
+    fn pack_rebuilds_an_unpacked_gob() {
+        let dir = env::temp_dir().join(format!("cabal-project-test-{}", std::process::id()));
+        let project = dir.join("project");
+        fs::create_dir_all(&dir).unwrap();
+
+        let original = gob();
+        File::create(dir.join("test.gob")).unwrap().write_all(&original).unwrap();
+        let gob = Gob::open(dir.join("test.gob"), true).unwrap();
+
+        unpack(&gob, &project).unwrap();
+        for f in ["palettes/palette_0.pal", "bitmaps/0000_WALL1.png", "levels/level_0.ron", "raw/0005_99.bin", "trailing.bin"].iter() {
+            assert!(project.join(f).exists(), "{} not unpacked", f);
+        }
+        let info = fs::read_to_string(project.join("info.toml")).unwrap();
+        assert!(info.contains("depth = 6"));
+        assert_eq!(info.matches("kind = \"raw\"").count(), 1);
+
+        let mut packed = Vec::new();
+        pack(&project, &mut packed).unwrap();
+        let refused = unpack(&gob, &project);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(packed == original);
+        assert!(refused.is_err());
+    }
+}